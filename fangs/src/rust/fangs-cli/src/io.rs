@@ -0,0 +1,213 @@
+use fangs_core::{FeatureAllocation, Samples};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+
+fn read_json(path: &str) -> Result<Value, String> {
+    let mut text = String::new();
+    let result = if path == "-" {
+        std::io::stdin().read_to_string(&mut text).map(|_| ())
+    } else {
+        std::fs::File::open(path).and_then(|mut file| file.read_to_string(&mut text).map(|_| ()))
+    };
+    result.map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Cannot parse '{}' as JSON: {}", path, e))
+}
+
+/// A feature allocation from a JSON array of rows (one per item), each an array of 0s and 1s.
+fn parse_allocation(value: &Value) -> Result<FeatureAllocation, String> {
+    let msg = "A feature allocation must be an array of rows, each an array of 0s and 1s.";
+    let rows = value.as_array().ok_or(msg)?;
+    let mut data = Vec::new();
+    let mut n_features = None;
+    for row in rows {
+        let row = row.as_array().ok_or(msg)?;
+        if *n_features.get_or_insert(row.len()) != row.len() {
+            return Err("All rows of a feature allocation must have the same length.".into());
+        }
+        for x in row {
+            data.push(x.as_f64().ok_or(msg)?);
+        }
+    }
+    let n_items = rows.len();
+    let n_features = n_features.unwrap_or(0);
+    // The data is in row-major order, so read it as the transpose.
+    let transpose = FeatureAllocation::from_column_major(n_features, n_items, &data)
+        .map_err(|e| e.to_string())?;
+    Ok(FeatureAllocation::from_fn(n_items, n_features, |i, j| {
+        transpose.get(j, i)
+    }))
+}
+
+/// Reads a feature allocation from a JSON file (or standard input if `path` is `-`).
+pub fn read_allocation(path: &str) -> Result<FeatureAllocation, String> {
+    parse_allocation(&read_json(path)?).map_err(|e| format!("In '{}': {}", path, e))
+}
+
+/// Reads feature allocations from a JSON file (or standard input if `path` is `-`) containing an
+/// array of feature allocations.
+pub fn read_allocations(path: &str) -> Result<Vec<FeatureAllocation>, String> {
+    read_json(path)?
+        .as_array()
+        .ok_or_else(|| format!("In '{}': Expected an array of feature allocations.", path))?
+        .iter()
+        .map(parse_allocation)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("In '{}': {}", path, e))
+}
+
+/// Reads importance weights from a JSON file (or standard input if `path` is `-`) containing an
+/// array of numbers.
+pub fn read_weights(path: &str) -> Result<Vec<f64>, String> {
+    read_json(path)?
+        .as_array()
+        .and_then(|x| x.iter().map(|w| w.as_f64()).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| format!("In '{}': Weights must be an array of numbers.", path))
+}
+
+/// Reads posterior samples from a JSON file (or standard input if `path` is `-`) containing an
+/// array of feature allocations, with optional importance weights from a JSON file containing an
+/// array of numbers.
+pub fn read_samples(path: &str, weights_path: Option<&str>) -> Result<Samples, String> {
+    let samples = read_allocations(path)?;
+    match weights_path {
+        None => Samples::new(samples).map_err(|e| format!("In '{}': {}", path, e)),
+        Some(weights_path) => {
+            let weights = read_weights(weights_path)?;
+            Samples::with_weights(samples, weights)
+                .map_err(|e| format!("In '{}': {}", weights_path, e))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Named results, which are scalars, vectors, or feature allocations.
+#[derive(Default)]
+pub struct Output {
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Output {
+    pub fn scalar(&mut self, name: &'static str, value: impl Into<Value>) {
+        self.fields.push((name, value.into()));
+    }
+
+    pub fn vector<T: Into<Value>>(
+        &mut self,
+        name: &'static str,
+        values: impl IntoIterator<Item = T>,
+    ) {
+        self.fields.push((
+            name,
+            Value::Array(values.into_iter().map(|x| x.into()).collect()),
+        ));
+    }
+
+    pub fn allocation(&mut self, name: &'static str, z: &FeatureAllocation) {
+        let rows: Vec<Value> = (0..z.n_items())
+            .map(|i| {
+                json!((0..z.n_features())
+                    .map(|j| u8::from(z.get(i, j)))
+                    .collect::<Vec<_>>())
+            })
+            .collect();
+        self.fields.push((name, Value::Array(rows)));
+    }
+
+    fn to_json(&self) -> String {
+        let map = self
+            .fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        let mut text = serde_json::to_string_pretty(&Value::Object(map)).unwrap();
+        text.push('\n');
+        text
+    }
+
+    /// Long format with one line per scalar, with row indices for vector elements, and with row
+    /// and column indices for matrix elements.  Indices are one-based.
+    fn to_csv(&self) -> String {
+        let mut text = String::from("name,row,column,value\n");
+        for (name, value) in &self.fields {
+            match value {
+                Value::Array(elements) => {
+                    for (i, element) in elements.iter().enumerate() {
+                        match element {
+                            Value::Array(row) => {
+                                for (j, x) in row.iter().enumerate() {
+                                    text.push_str(&format!("{},{},{},{}\n", name, i + 1, j + 1, x));
+                                }
+                            }
+                            x => text.push_str(&format!("{},{},,{}\n", name, i + 1, x)),
+                        }
+                    }
+                }
+                x => text.push_str(&format!("{},,,{}\n", name, x)),
+            }
+        }
+        text
+    }
+
+    /// Writes to the file at `path`, or to standard output if `path` is `None` or `-`.
+    pub fn write(&self, format: Format, path: Option<&str>) -> Result<(), String> {
+        let text = match format {
+            Format::Json => self.to_json(),
+            Format::Csv => self.to_csv(),
+        };
+        match path {
+            None | Some("-") => std::io::stdout()
+                .write_all(text.as_bytes())
+                .map_err(|e| format!("Cannot write output: {}", e)),
+            Some(path) => {
+                std::fs::write(path, text).map_err(|e| format!("Cannot write '{}': {}", path, e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_round_trip() {
+        let value = json!([[0, 1], [1, 1], [0, 0]]);
+        let z = parse_allocation(&value).unwrap();
+        assert_eq!((z.n_items(), z.n_features()), (3, 2));
+        assert!(z.get(0, 1) && !z.get(0, 0) && z.get(1, 0));
+        let mut output = Output::default();
+        output.allocation("estimate", &z);
+        output.scalar("loss", 0.5);
+        assert_eq!(output.fields[0].1, value);
+        assert!(output
+            .to_csv()
+            .starts_with("name,row,column,value\nestimate,1,1,0\nestimate,1,2,1\n"));
+        assert!(output.to_csv().ends_with("loss,,,0.5\n"));
+    }
+
+    #[test]
+    fn invalid_allocations() {
+        assert!(parse_allocation(&json!([[0, 1], [1]])).is_err());
+        assert!(parse_allocation(&json!([[0, 2]])).is_err());
+        assert!(parse_allocation(&json!({"a": 1})).is_err());
+        let z = parse_allocation(&json!([[], []])).unwrap();
+        assert_eq!((z.n_items(), z.n_features()), (2, 0));
+    }
+}
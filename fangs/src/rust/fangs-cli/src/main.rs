@@ -0,0 +1,342 @@
+mod io;
+mod options;
+
+use fangs_core::{
+    Algorithm, Fangs, FangsResult, FaroLoss, FeatureAllocation, Monitor, Samples, Schedule, Solver,
+};
+use io::{Format, Output};
+use options::Options;
+use std::io::Write;
+
+const USAGE: &str = "\
+Feature allocation neighborhood greedy search.
+
+Usage:
+  fangs search --samples FILE [--weights FILE] [--n-init 16] [--n-sweet 4]
+               [--n-iterations 0] [--max-seconds 60] [--a 1.0] [--n-cores 0]
+               [--algorithm stochastic] [--schedule geometric] [--temperature 0.5]
+               [--rate 0.995] [--target 0.2] [--column-moves 0] [--initial FILE]
+               [--mini-batch 0] [--mini-batch-period 100] [--max-features 0] [--seed N]
+               [--solver lapjv] [--checkpoint FILE] [--checkpoint-seconds 60] [--quiet]
+               [--format json] [--output FILE]
+  fangs resume --checkpoint FILE --samples FILE [--weights FILE] [--max-seconds 60]
+               [--n-cores 0] [--checkpoint-seconds 60] [--quiet] [--format json]
+               [--output FILE]
+  fangs loss --z1 FILE --z2 FILE [--a 1.0] [--augmented] [--format json] [--output FILE]
+  fangs loss-permutations --z1 FILE --z2 FILE [--a 1.0] [--format json] [--output FILE]
+  fangs expected-loss --estimate FILE --samples FILE [--weights FILE] [--a 1.0]
+                      [--n-cores 0] [--standard-error] [--format json] [--output FILE]
+  fangs pairwise-loss --samples FILE [--a 1.0] [--n-cores 0] [--format json] [--output FILE]
+  fangs lower-bound --samples FILE [--weights FILE] [--a 1.0] [--n-cores 0] [--format json]
+                    [--output FILE]
+
+A feature allocation is read as a JSON array of rows (one per item), each being an array of
+0s and 1s.  Samples are read as a JSON array of feature allocations, and their optional
+importance weights as a JSON array of nonnegative numbers.  Initial estimates are read as a
+JSON array of feature allocations.  A FILE of '-' denotes standard input or standard output.
+The algorithm is one of 'stochastic', 'annealing', 'deterministic', 'draws', 'double-greedy',
+or 'exhaustive', which evaluates every feature allocation with up to --max-features features
+(zero meaning the most features of any sample).  The annealing schedule is one of 'geometric'
+(using --rate), 'linear', or 'adaptive' (using --target).  The assignment problems defining
+the loss are solved by 'lapjv', 'hungarian', 'auction', or 'exhaustive' (for up to 10
+features).  Without a seed, one is chosen at random and reported in the output.  With a
+checkpoint, the state of the stochastic, annealing, or draws search is saved to the FILE
+periodically and when it stops, and 'resume' continues it with the same samples and weights.
+A positive --mini-batch scores each proposed change, including column moves, on a random
+mini-batch of that many distinct samples, with exact expected losses every
+--mini-batch-period iterations.  With --standard-error, 'expected-loss' also reports the
+variance of the losses, the naive and batch means standard errors of the expected loss, the
+effective sample size, and the number of batches, for which the samples should be in the
+order they were drawn.  The output format is either 'json' or 'csv'.
+";
+
+/// Prints status messages to standard error.
+struct Stderr;
+
+impl Monitor for Stderr {
+    fn print(&mut self, msg: &str) -> bool {
+        let mut stderr = std::io::stderr();
+        let _ = stderr.write_all(msg.as_bytes());
+        let _ = stderr.flush();
+        false
+    }
+
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
+fn parse_algorithm(x: &str) -> Result<Algorithm, String> {
+    match x {
+        "stochastic" => Ok(Algorithm::Stochastic),
+        "annealing" => Ok(Algorithm::Annealing),
+        "deterministic" => Ok(Algorithm::Deterministic),
+        "draws" => Ok(Algorithm::Draws),
+        "double-greedy" => Ok(Algorithm::DoubleGreedy),
+        "exhaustive" => Ok(Algorithm::Exhaustive),
+        _ => Err(format!("Unrecognized algorithm '{}'.", x)),
+    }
+}
+
+fn parse_schedule(options: &mut Options) -> Result<Schedule, String> {
+    let schedule = options.value("schedule", "geometric".to_string())?;
+    let temperature = options.value("temperature", 0.5)?;
+    let rate = options.value("rate", 0.995)?;
+    let target = options.value("target", 0.2)?;
+    match schedule.as_str() {
+        "geometric" => Ok(Schedule::Geometric { temperature, rate }),
+        "linear" => Ok(Schedule::Linear { temperature }),
+        "adaptive" => Ok(Schedule::Adaptive {
+            temperature,
+            target,
+        }),
+        _ => Err(format!("Unrecognized schedule '{}'.", schedule)),
+    }
+}
+
+fn parse_loss(options: &mut Options) -> Result<FaroLoss, String> {
+    FaroLoss::new(options.value("a", 1.0)?).map_err(|e| e.to_string())
+}
+
+fn parse_solver(x: &str) -> Result<Solver, String> {
+    match x {
+        "lapjv" => Ok(Solver::Lapjv),
+        "hungarian" => Ok(Solver::Hungarian),
+        "auction" => Ok(Solver::Auction),
+        "exhaustive" => Ok(Solver::Exhaustive),
+        _ => Err(format!("Unrecognized solver '{}'.", x)),
+    }
+}
+
+fn search(mut options: Options) -> Result<Output, String> {
+    let samples = io::read_samples(
+        &options.required::<String>("samples")?,
+        options.optional::<String>("weights")?.as_deref(),
+    )?;
+    let algorithm = parse_algorithm(&options.value("algorithm", "stochastic".to_string())?)?;
+    let solver = parse_solver(&options.value("solver", "lapjv".to_string())?)?;
+    let mut fangs = Fangs::new()
+        .loss(parse_loss(&mut options)?.with_solver(solver))
+        .n_init(options.value("n-init", 16)?)
+        .n_sweet(options.value("n-sweet", 4)?)
+        .n_iterations(options.value("n-iterations", 0)?)
+        .max_seconds(options.value("max-seconds", 60.0)?)
+        .n_cores(options.value("n-cores", 0)?)
+        .algorithm(algorithm)
+        .schedule(parse_schedule(&mut options)?)
+        .column_moves(options.value("column-moves", 0.0)?)
+        .mini_batch(
+            options.value("mini-batch", 0)?,
+            options.value("mini-batch-period", 100)?,
+        )
+        .max_features(options.value("max-features", 0)?)
+        .quiet(options.flag("quiet"));
+    if let Some(path) = options.optional::<String>("initial")? {
+        fangs = fangs.initial_estimates(io::read_allocations(&path)?);
+    }
+    if let Some(seed) = options.optional("seed")? {
+        fangs = fangs.seed(seed);
+    }
+    if let Some(path) = options.optional::<String>("checkpoint")? {
+        fangs = fangs.checkpoint(path, options.value("checkpoint-seconds", 60.0)?);
+    }
+    options.finish()?;
+    let result = fangs
+        .run(&samples, &mut Stderr)
+        .map_err(|e| e.to_string())?;
+    Ok(search_output(&result, &samples))
+}
+
+fn resume(mut options: Options) -> Result<Output, String> {
+    let path = options.required::<String>("checkpoint")?;
+    let samples = io::read_samples(
+        &options.required::<String>("samples")?,
+        options.optional::<String>("weights")?.as_deref(),
+    )?;
+    let fangs = Fangs::new()
+        .max_seconds(options.value("max-seconds", 60.0)?)
+        .n_cores(options.value("n-cores", 0)?)
+        .checkpoint(&path, options.value("checkpoint-seconds", 60.0)?)
+        .quiet(options.flag("quiet"));
+    options.finish()?;
+    let result = fangs
+        .resume(&path, &samples, &mut Stderr)
+        .map_err(|e| e.to_string())?;
+    Ok(search_output(&result, &samples))
+}
+
+fn search_output(result: &FangsResult, samples: &Samples) -> Output {
+    let mut output = Output::default();
+    output.allocation("estimate", &result.estimate);
+    output.scalar("expectedLoss", result.expected_loss);
+    output.scalar("iteration", result.iteration);
+    output.scalar("nIterations", result.n_iterations);
+    output.scalar("secondsInitialization", result.seconds_initialization);
+    output.scalar("secondsAlignment", result.seconds_alignment);
+    output.scalar("secondsRanking", result.seconds_ranking);
+    output.scalar("secondsSweetening", result.seconds_sweetening);
+    output.scalar("secondsTotal", result.seconds_total);
+    output.scalar("truncatedAlignment", result.truncated.alignment);
+    output.scalar("truncatedRanking", result.truncated.ranking);
+    output.scalar("truncatedSweetening", result.truncated.sweetening);
+    output.scalar("whichSweet", result.which_sweet + 1);
+    output.scalar("nAccepts", result.n_accepts);
+    output.scalar("nRejects", result.n_rejects);
+    output.scalar("nUniqueSamples", samples.n_unique());
+    output.scalar("seed", result.seed);
+    output
+}
+
+fn loss(mut options: Options) -> Result<Output, String> {
+    let z1 = io::read_allocation(&options.required::<String>("z1")?)?;
+    let z2 = io::read_allocation(&options.required::<String>("z2")?)?;
+    let faro = parse_loss(&mut options)?;
+    let augmented = options.flag("augmented");
+    options.finish()?;
+    let mut output = Output::default();
+    if augmented {
+        let (loss, permutation1, permutation2) =
+            faro.loss_augmented(&z1, &z2).map_err(|e| e.to_string())?;
+        output.scalar("loss", loss);
+        output.vector("permutation1", permutation2.iter().map(|x| x + 1));
+        output.vector("permutation2", permutation1.iter().map(|x| x + 1));
+    } else {
+        output.scalar("loss", faro.loss(&z1, &z2).map_err(|e| e.to_string())?);
+    }
+    Ok(output)
+}
+
+fn loss_permutations(mut options: Options) -> Result<Output, String> {
+    let z1 = io::read_allocation(&options.required::<String>("z1")?)?;
+    let z2 = io::read_allocation(&options.required::<String>("z2")?)?;
+    let faro = parse_loss(&mut options)?;
+    options.finish()?;
+    let mut output = Output::default();
+    output.scalar(
+        "loss",
+        faro.loss_permutations(&z1, &z2)
+            .map_err(|e| e.to_string())?,
+    );
+    Ok(output)
+}
+
+fn expected_loss(mut options: Options) -> Result<Output, String> {
+    let z = io::read_allocation(&options.required::<String>("estimate")?)?;
+    if options.flag("standard-error") {
+        return standard_error(z, options);
+    }
+    let samples = io::read_samples(
+        &options.required::<String>("samples")?,
+        options.optional::<String>("weights")?.as_deref(),
+    )?;
+    let faro = parse_loss(&mut options)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.value("n-cores", 0)?)
+        .build()
+        .map_err(|e| e.to_string())?;
+    options.finish()?;
+    let mut output = Output::default();
+    output.scalar(
+        "expectedLoss",
+        faro.expected_loss(&z, &samples, &pool)
+            .map_err(|e| e.to_string())?,
+    );
+    Ok(output)
+}
+
+fn standard_error(z: FeatureAllocation, mut options: Options) -> Result<Output, String> {
+    let samples = io::read_allocations(&options.required::<String>("samples")?)?;
+    let weights = match options.optional::<String>("weights")? {
+        Some(path) => io::read_weights(&path)?,
+        None => vec![1.0; samples.len()],
+    };
+    let faro = parse_loss(&mut options)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.value("n-cores", 0)?)
+        .build()
+        .map_err(|e| e.to_string())?;
+    options.finish()?;
+    let x = faro
+        .expected_loss_standard_error(&z, &samples, &weights, &pool)
+        .map_err(|e| e.to_string())?;
+    let mut output = Output::default();
+    output.scalar("expectedLoss", x.expected_loss);
+    output.scalar("variance", x.variance);
+    output.scalar("se", x.standard_error);
+    output.scalar("batchMeansSE", x.batch_means_standard_error);
+    output.scalar("effectiveSampleSize", x.effective_sample_size);
+    output.scalar("nBatches", x.n_batches);
+    Ok(output)
+}
+
+fn pairwise_loss(mut options: Options) -> Result<Output, String> {
+    let samples = io::read_allocations(&options.required::<String>("samples")?)?;
+    let faro = parse_loss(&mut options)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.value("n-cores", 0)?)
+        .build()
+        .map_err(|e| e.to_string())?;
+    options.finish()?;
+    let losses = faro
+        .pairwise_losses(&samples, &pool)
+        .map_err(|e| e.to_string())?;
+    let (medoid, medoid_expected_loss) = fangs_core::medoid(&losses, &vec![1.0; samples.len()]);
+    let mut output = Output::default();
+    output.vector("losses", losses.rows().into_iter().map(|row| row.to_vec()));
+    output.scalar("medoid", medoid + 1);
+    output.scalar("medoidExpectedLoss", medoid_expected_loss);
+    Ok(output)
+}
+
+fn lower_bound(mut options: Options) -> Result<Output, String> {
+    let samples = io::read_samples(
+        &options.required::<String>("samples")?,
+        options.optional::<String>("weights")?.as_deref(),
+    )?;
+    let faro = parse_loss(&mut options)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.value("n-cores", 0)?)
+        .build()
+        .map_err(|e| e.to_string())?;
+    options.finish()?;
+    let mut output = Output::default();
+    output.scalar(
+        "lowerBound",
+        faro.lower_bound(&samples, &pool)
+            .map_err(|e| e.to_string())?,
+    );
+    Ok(output)
+}
+
+fn run(command: &str, args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut options = Options::parse(args, &["quiet", "augmented", "standard-error"])?;
+    let format = options.value("format", Format::Json)?;
+    let path: Option<String> = options.optional("output")?;
+    let output = match command {
+        "search" => search(options),
+        "resume" => resume(options),
+        "loss" => loss(options),
+        "loss-permutations" => loss_permutations(options),
+        "expected-loss" => expected_loss(options),
+        "pairwise-loss" => pairwise_loss(options),
+        "lower-bound" => lower_bound(options),
+        _ => Err(format!("Unrecognized command '{}'.\n\n{}", command, USAGE)),
+    }?;
+    output.write(format, path.as_deref())
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    let result = match command.as_deref() {
+        None | Some("help") | Some("--help") | Some("-h") => {
+            print!("{}", USAGE);
+            return;
+        }
+        Some(command) => run(command, args),
+    };
+    if let Err(msg) = result {
+        eprintln!("Error: {}", msg);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,98 @@
+use std::str::FromStr;
+
+/// Command-line options of the form `--name value`, or just `--name` for a flag.
+pub struct Options {
+    values: Vec<(String, Option<String>)>,
+}
+
+impl Options {
+    /// Parses `args`, where `flags` lists the names of options which do not take a value.
+    pub fn parse(args: impl Iterator<Item = String>, flags: &[&str]) -> Result<Self, String> {
+        let mut values = Vec::new();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => return Err(format!("Expected an option, but found '{}'.", arg)),
+            };
+            if values.iter().any(|(x, _)| *x == name) {
+                return Err(format!("Option '--{}' is given more than once.", name));
+            }
+            if flags.contains(&name.as_str()) {
+                values.push((name, None));
+            } else {
+                match args.next() {
+                    Some(value) => values.push((name, Some(value))),
+                    None => return Err(format!("Option '--{}' requires a value.", name)),
+                }
+            }
+        }
+        Ok(Self { values })
+    }
+
+    fn take(&mut self, name: &str) -> Option<Option<String>> {
+        let index = self.values.iter().position(|(x, _)| x == name)?;
+        Some(self.values.swap_remove(index).1)
+    }
+
+    pub fn flag(&mut self, name: &str) -> bool {
+        self.take(name).is_some()
+    }
+
+    pub fn optional<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, String> {
+        match self.take(name).flatten() {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Cannot parse '{}' for option '--{}'.", value, name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn value<T: FromStr>(&mut self, name: &str, default: T) -> Result<T, String> {
+        Ok(self.optional(name)?.unwrap_or(default))
+    }
+
+    pub fn required<T: FromStr>(&mut self, name: &str) -> Result<T, String> {
+        self.optional(name)?
+            .ok_or_else(|| format!("Option '--{}' is required.", name))
+    }
+
+    /// Fails if any options remain which were not used.
+    pub fn finish(self) -> Result<(), String> {
+        match self.values.first() {
+            Some((name, _)) => Err(format!("Unrecognized option '--{}'.", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|x| x.to_string()), &["quiet"])
+    }
+
+    #[test]
+    fn values_and_flags() {
+        let mut options = parse(&["--a", "0.5", "--quiet", "--samples", "x.json"]).unwrap();
+        assert_eq!(options.value("a", 1.0), Ok(0.5));
+        assert_eq!(options.value("n-cores", 0_usize), Ok(0));
+        assert!(options.flag("quiet"));
+        assert_eq!(options.required::<String>("samples").unwrap(), "x.json");
+        assert!(options.finish().is_ok());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&["--samples"]).is_err());
+        assert!(parse(&["samples"]).is_err());
+        assert!(parse(&["--a", "1", "--a", "1"]).is_err());
+        let mut options = parse(&["--a", "one", "--other", "x"]).unwrap();
+        assert!(options.value("a", 1.0).is_err());
+        assert!(options.required::<String>("samples").is_err());
+        assert!(options.finish().is_err());
+    }
+}
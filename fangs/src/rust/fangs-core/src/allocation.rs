@@ -0,0 +1,226 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+
+const BITS: usize = u64::BITS as usize;
+
+/// A feature allocation in binary matrix form, with items in the rows and features in the
+/// columns.
+///
+/// Each column is stored as a bitset, so that the number of items on which two features disagree
+/// is computed with XOR/AND and popcount.  Bits beyond the number of items are always zero.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FeatureAllocation {
+    n_items: usize,
+    n_features: usize,
+    n_words: usize,
+    bits: Vec<u64>,
+}
+
+impl FeatureAllocation {
+    /// A feature allocation in which no item has any of the `n_features` features.
+    pub fn zeros(n_items: usize, n_features: usize) -> Self {
+        let n_words = (n_items + BITS - 1) / BITS;
+        Self {
+            n_items,
+            n_features,
+            n_words,
+            bits: vec![0; n_words * n_features],
+        }
+    }
+
+    /// A feature allocation whose element `(i, j)` indicates whether item `i` has feature `j`.
+    pub fn from_fn(n_items: usize, n_features: usize, f: impl Fn(usize, usize) -> bool) -> Self {
+        let mut z = Self::zeros(n_items, n_features);
+        for j in 0..n_features {
+            for i in 0..n_items {
+                if f(i, j) {
+                    z.set(i, j, true);
+                }
+            }
+        }
+        z
+    }
+
+    /// A feature allocation from a binary matrix stored in column-major order (as in R).
+    #[allow(clippy::float_cmp)]
+    pub fn from_column_major(
+        n_items: usize,
+        n_features: usize,
+        data: &[f64],
+    ) -> Result<Self, Error> {
+        if data.len() != n_items * n_features {
+            return Err(Error::InconsistentDimensions);
+        }
+        if data.iter().any(|&x| x != 0.0 && x != 1.0) {
+            return Err(Error::NotBinary);
+        }
+        Ok(Self::from_fn(n_items, n_features, |i, j| {
+            data[j * n_items + i] != 0.0
+        }))
+    }
+
+    /// The elements of the binary matrix in column-major order (as in R).
+    pub fn to_column_major(&self) -> Vec<f64> {
+        (0..self.n_features())
+            .flat_map(|j| (0..self.n_items).map(move |i| if self.get(i, j) { 1.0 } else { 0.0 }))
+            .collect()
+    }
+
+    pub fn n_items(&self) -> usize {
+        self.n_items
+    }
+
+    pub fn n_features(&self) -> usize {
+        self.n_features
+    }
+
+    /// Does item `i` have feature `j`?
+    pub fn get(&self, i: usize, j: usize) -> bool {
+        debug_assert!(i < self.n_items);
+        self.column_bits(j)[i / BITS] & (1 << (i % BITS)) != 0
+    }
+
+    pub fn set(&mut self, i: usize, j: usize, value: bool) {
+        debug_assert!(i < self.n_items);
+        let word = &mut self.bits[j * self.n_words + i / BITS];
+        let mask = 1 << (i % BITS);
+        if value {
+            *word |= mask;
+        } else {
+            *word &= !mask;
+        }
+    }
+
+    /// Toggle whether item `i` has feature `j`.
+    pub fn flip(&mut self, i: usize, j: usize) {
+        debug_assert!(i < self.n_items);
+        self.bits[j * self.n_words + i / BITS] ^= 1 << (i % BITS);
+    }
+
+    /// Whether each item has feature `j`.
+    pub fn column(&self, j: usize) -> Vec<bool> {
+        (0..self.n_items()).map(|i| self.get(i, j)).collect()
+    }
+
+    /// Adds a feature, where `column` indicates whether each item has it.
+    pub fn push_column(&mut self, column: &[bool]) {
+        assert_eq!(column.len(), self.n_items());
+        let j = self.n_features;
+        self.n_features += 1;
+        self.bits.resize(self.bits.len() + self.n_words, 0);
+        for (i, &x) in column.iter().enumerate() {
+            if x {
+                self.set(i, j, true);
+            }
+        }
+    }
+
+    /// Removes feature `j`, replacing it with the last feature.
+    pub fn swap_remove_column(&mut self, j: usize) {
+        let n_words = self.n_words;
+        let last = self.n_features() - 1;
+        if j != last {
+            self.bits
+                .copy_within(last * n_words..(last + 1) * n_words, j * n_words);
+        }
+        self.bits.truncate(last * n_words);
+        self.n_features = last;
+    }
+
+    /// Is no item allocated to feature `j`?
+    pub fn is_empty_column(&self, j: usize) -> bool {
+        self.column_bits(j).iter().all(|&x| x == 0)
+    }
+
+    /// The same feature allocation with all features that have no items removed.
+    pub fn without_empty_columns(&self) -> Self {
+        let mut z = Self::zeros(self.n_items, 0);
+        for j in 0..self.n_features() {
+            if !self.is_empty_column(j) {
+                z.bits.extend_from_slice(self.column_bits(j));
+                z.n_features += 1;
+            }
+        }
+        z
+    }
+
+    /// The same feature allocation in left-ordered form, i.e., without features having no items
+    /// and with the features sorted by the first item on which they differ, where the feature
+    /// having that item comes first.  Feature allocations which differ only in the order of their
+    /// features and in features having no items have the same left-ordered form.
+    pub fn left_ordered(&self) -> Self {
+        let mut columns: Vec<&[u64]> = (0..self.n_features)
+            .filter(|&j| !self.is_empty_column(j))
+            .map(|j| self.column_bits(j))
+            .collect();
+        columns.sort_unstable_by(|x, y| {
+            match x.iter().zip(y.iter()).find(|(&x, &y)| x != y) {
+                // The lowest differing bit is the first item on which the features differ.
+                Some((&x, &y)) => {
+                    let lowest = (x ^ y) & (x ^ y).wrapping_neg();
+                    if x & lowest != 0 {
+                        std::cmp::Ordering::Less
+                    } else {
+                        std::cmp::Ordering::Greater
+                    }
+                }
+                None => std::cmp::Ordering::Equal,
+            }
+        });
+        let mut z = Self::zeros(self.n_items, 0);
+        for column in columns {
+            z.bits.extend_from_slice(column);
+            z.n_features += 1;
+        }
+        z
+    }
+
+    /// The bitset of items having feature `j`.
+    pub(crate) fn column_bits(&self, j: usize) -> &[u64] {
+        &self.bits[j * self.n_words..(j + 1) * self.n_words]
+    }
+
+    /// The number of items having feature `j`.
+    pub(crate) fn count_ones(&self, j: usize) -> usize {
+        self.column_bits(j)
+            .iter()
+            .map(|x| x.count_ones() as usize)
+            .sum()
+    }
+
+    /// The word of a column's bitset holding item `i`, and the mask of its bit in that word.
+    pub(crate) fn bit_position(i: usize) -> (usize, u64) {
+        (i / BITS, 1 << (i % BITS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitsets_across_words() {
+        let n_items = 130;
+        let f = |i: usize, j: usize| (i * (j + 3)) % 7 == 0;
+        let mut z = FeatureAllocation::from_fn(n_items, 3, f);
+        assert_eq!((z.n_items(), z.n_features()), (n_items, 3));
+        assert!((0..n_items).all(|i| (0..3).all(|j| z.get(i, j) == f(i, j))));
+        assert_eq!(z.count_ones(0), (0..n_items).filter(|&i| f(i, 0)).count());
+        let data = z.to_column_major();
+        assert_eq!(
+            FeatureAllocation::from_column_major(n_items, 3, &data).unwrap(),
+            z
+        );
+        z.flip(129, 1);
+        assert_eq!(z.get(129, 1), !f(129, 1));
+        z.push_column(&vec![false; n_items]);
+        z.swap_remove_column(0);
+        assert_eq!(z.n_features(), 3);
+        assert!(z.is_empty_column(0));
+        assert_eq!(z.without_empty_columns().n_features(), 2);
+        assert_eq!(
+            FeatureAllocation::from_column_major(2, 1, &[0.0, 0.5]),
+            Err(Error::NotBinary)
+        );
+    }
+}
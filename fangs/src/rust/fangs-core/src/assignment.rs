@@ -0,0 +1,419 @@
+use crate::loss::{Cancellation, SolverState, WeightMatrix};
+use serde::{Deserialize, Serialize};
+
+/// An algorithm for the linear assignment problem defining the FARO loss.  All give the same
+/// loss, up to rounding for [`Solver::Auction`], but may differ in the optimal permutations when
+/// there are ties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Solver {
+    /// The shortest augmenting path algorithm of Jonker and Volgenant, which is warm-started when
+    /// rows of a weight matrix change.
+    #[default]
+    Lapjv,
+    /// The Hungarian (Kuhn-Munkres) algorithm, which has little overhead for small problems.
+    Hungarian,
+    /// The auction algorithm of Bertsekas with epsilon scaling, in which every unassigned row
+    /// bids in each round against the same prices.
+    Auction,
+    /// Evaluates every permutation of the features, which is feasible only for a handful of
+    /// features, so it is limited to [`Solver::max_features`].
+    Exhaustive,
+}
+
+impl Solver {
+    /// The largest number of features of a feature allocation whose loss the solver computes,
+    /// beyond which [`crate::Error::TooLarge`] is returned.
+    pub fn max_features(self) -> usize {
+        match self {
+            Solver::Exhaustive => 10,
+            _ => usize::MAX,
+        }
+    }
+
+    fn algorithm(self) -> &'static dyn AssignmentSolver {
+        match self {
+            Solver::Lapjv => &Lapjv,
+            Solver::Hungarian => &Hungarian,
+            Solver::Auction => &Auction,
+            Solver::Exhaustive => &Exhaustive,
+        }
+    }
+
+    /// The solution of the assignment problem, or `None` if `cancellation` is cancelled first.
+    pub(crate) fn solve(
+        self,
+        w: &WeightMatrix,
+        cancellation: &Cancellation,
+    ) -> Option<SolverState> {
+        self.algorithm().solve(w, cancellation)
+    }
+
+    /// Re-solves the assignment problem after rows `rows` changed, starting from `state`, which
+    /// is left in an unspecified state if `cancellation` is cancelled first.
+    pub(crate) fn update(
+        self,
+        w: &WeightMatrix,
+        state: &mut SolverState,
+        rows: &[usize],
+        cancellation: &Cancellation,
+    ) -> Option<()> {
+        self.algorithm().update(w, state, rows, cancellation)
+    }
+}
+
+/// Solves the linear assignment problem of a weight matrix, whose solution assigns each of the
+/// rows `0..w.dim()` to one of the columns `0..w.dim()`, where rows and columns beyond those of
+/// the costs stand for being unmatched.
+pub(crate) trait AssignmentSolver: Sync {
+    fn solve(&self, w: &WeightMatrix, cancellation: &Cancellation) -> Option<SolverState>;
+
+    /// Re-solves after rows `rows` of the weight matrix changed, which by default starts over.
+    fn update(
+        &self,
+        w: &WeightMatrix,
+        state: &mut SolverState,
+        _rows: &[usize],
+        cancellation: &Cancellation,
+    ) -> Option<()> {
+        *state = self.solve(w, cancellation)?;
+        Some(())
+    }
+}
+
+/// The state of a solver which keeps no duals, given the column assigned to each row.
+fn from_assignment(in_row: Vec<usize>) -> SolverState {
+    let mut in_col = vec![0; in_row.len()];
+    for (i, &j) in in_row.iter().enumerate() {
+        in_col[j] = i;
+    }
+    SolverState::from_parts(Vec::new(), in_row, in_col)
+}
+
+struct Lapjv;
+
+impl AssignmentSolver for Lapjv {
+    fn solve(&self, w: &WeightMatrix, cancellation: &Cancellation) -> Option<SolverState> {
+        unless_cancelled(w.lapjv(cancellation).solve_with_state())
+    }
+
+    fn update(
+        &self,
+        w: &WeightMatrix,
+        state: &mut SolverState,
+        rows: &[usize],
+        cancellation: &Cancellation,
+    ) -> Option<()> {
+        let solver = w
+            .lapjv(cancellation)
+            .with_state(std::mem::take(state), rows);
+        *state = unless_cancelled(solver.solve_with_state())?;
+        Some(())
+    }
+}
+
+/// The solution of the linear assignment problem, or `None` if it was cancelled.
+fn unless_cancelled<T>(result: Result<T, lapjv::LapJVError>) -> Option<T> {
+    match result {
+        Ok(x) => Some(x),
+        Err(e) if matches!(e.kind(), lapjv::ErrorKind::Cancelled) => None,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// The O(n^3) version of the Hungarian algorithm, which adds the rows one at a time, each by a
+/// shortest augmenting path with respect to the reduced costs.
+struct Hungarian;
+
+impl AssignmentSolver for Hungarian {
+    fn solve(&self, w: &WeightMatrix, cancellation: &Cancellation) -> Option<SolverState> {
+        let n = w.dim();
+        // Rows and columns are numbered from one, so that zero is a sentinel column whose row is
+        // the one being added.
+        let mut u = vec![0.0; n + 1];
+        let mut v = vec![0.0; n + 1];
+        let mut row_of = vec![0; n + 1];
+        let mut way = vec![0; n + 1];
+        for i in 1..=n {
+            if cancellation.is_cancelled() {
+                return None;
+            }
+            row_of[0] = i;
+            let mut j0 = 0;
+            let mut min_v = vec![f64::INFINITY; n + 1];
+            let mut used = vec![false; n + 1];
+            loop {
+                used[j0] = true;
+                let i0 = row_of[j0];
+                let mut delta = f64::INFINITY;
+                let mut j1 = 0;
+                for j in 1..=n {
+                    if !used[j] {
+                        let reduced = w.entry(i0 - 1, j - 1) - u[i0] - v[j];
+                        if reduced < min_v[j] {
+                            min_v[j] = reduced;
+                            way[j] = j0;
+                        }
+                        if min_v[j] < delta {
+                            delta = min_v[j];
+                            j1 = j;
+                        }
+                    }
+                }
+                for j in 0..=n {
+                    if used[j] {
+                        u[row_of[j]] += delta;
+                        v[j] -= delta;
+                    } else {
+                        min_v[j] -= delta;
+                    }
+                }
+                j0 = j1;
+                if row_of[j0] == 0 {
+                    break;
+                }
+            }
+            // Augment along the path back to the sentinel column.
+            while j0 != 0 {
+                let j1 = way[j0];
+                row_of[j0] = row_of[j1];
+                j0 = j1;
+            }
+        }
+        let mut in_row = vec![0; n];
+        for j in 1..=n {
+            in_row[row_of[j] - 1] = j - 1;
+        }
+        Some(from_assignment(in_row))
+    }
+}
+
+/// The auction algorithm with epsilon scaling, in which the unassigned rows bid for columns in
+/// rounds, one row after another but against the prices at the start of the round.  The final
+/// epsilon is so small relative to the costs that the solution is optimal up to rounding.
+struct Auction;
+
+impl AssignmentSolver for Auction {
+    fn solve(&self, w: &WeightMatrix, cancellation: &Cancellation) -> Option<SolverState> {
+        let n = w.dim();
+        let scale = (0..n)
+            .flat_map(|i| (0..n).map(move |j| (i, j)))
+            .fold(0.0, |scale: f64, (i, j)| scale.max(w.entry(i, j).abs()));
+        let final_epsilon = scale.max(1.0) * 1e-12;
+        let mut epsilon = scale.max(final_epsilon) / 4.0;
+        let mut prices = vec![0.0; n];
+        loop {
+            epsilon = epsilon.max(final_epsilon);
+            let mut in_row = vec![usize::MAX; n];
+            let mut in_col = vec![usize::MAX; n];
+            let mut unassigned: Vec<usize> = (0..n).rev().collect();
+            while !unassigned.is_empty() {
+                if cancellation.is_cancelled() {
+                    return None;
+                }
+                // Each unassigned row bids for its best column, raising its price by the margin
+                // over the second best column plus epsilon.
+                let bids: Vec<(usize, usize, f64)> = unassigned
+                    .drain(..)
+                    .map(|i| {
+                        let (mut best, mut first, mut second) = (0, f64::INFINITY, f64::INFINITY);
+                        for (j, price) in prices.iter().enumerate() {
+                            let value = w.entry(i, j) + price;
+                            if value < first {
+                                (best, first, second) = (j, value, first);
+                            } else if value < second {
+                                second = value;
+                            }
+                        }
+                        let margin = if second.is_finite() {
+                            second - first
+                        } else {
+                            0.0
+                        };
+                        (i, best, prices[best] + margin + epsilon)
+                    })
+                    .collect();
+                // Each column goes to its highest bidder, and the rest bid again.
+                let mut winners: Vec<Option<(usize, f64)>> = vec![None; n];
+                for &(i, j, bid) in &bids {
+                    match winners[j] {
+                        Some((_, highest)) if highest >= bid => unassigned.push(i),
+                        Some((loser, _)) => {
+                            unassigned.push(loser);
+                            winners[j] = Some((i, bid));
+                        }
+                        None => winners[j] = Some((i, bid)),
+                    }
+                }
+                for (j, winner) in winners.into_iter().enumerate() {
+                    if let Some((i, bid)) = winner {
+                        if in_col[j] != usize::MAX {
+                            in_row[in_col[j]] = usize::MAX;
+                            unassigned.push(in_col[j]);
+                        }
+                        in_row[i] = j;
+                        in_col[j] = i;
+                        prices[j] = bid;
+                    }
+                }
+            }
+            if epsilon <= final_epsilon {
+                return Some(from_assignment(in_row));
+            }
+            epsilon /= 4.0;
+        }
+    }
+}
+
+/// Evaluates every permutation of the columns.
+struct Exhaustive;
+
+impl AssignmentSolver for Exhaustive {
+    fn solve(&self, w: &WeightMatrix, cancellation: &Cancellation) -> Option<SolverState> {
+        use itertools::Itertools;
+        let n = w.dim();
+        let mut best = (f64::INFINITY, (0..n).collect());
+        for (count, permutation) in (0..n).permutations(n).enumerate() {
+            if count % 1024 == 0 && cancellation.is_cancelled() {
+                return None;
+            }
+            let cost = permutation
+                .iter()
+                .enumerate()
+                .fold(0.0, |cost, (i, &j)| cost + w.entry(i, j));
+            if cost < best.0 {
+                best = (cost, permutation);
+            }
+        }
+        Some(from_assignment(best.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loss::make_weight_matrix;
+    use crate::FeatureAllocation;
+    use approx::assert_relative_eq;
+    use rand::prelude::*;
+    use rand_pcg::Pcg64Mcg;
+
+    const SOLVERS: [Solver; 4] = [
+        Solver::Lapjv,
+        Solver::Hungarian,
+        Solver::Auction,
+        Solver::Exhaustive,
+    ];
+
+    fn random(n_items: usize, n_features: usize, rng: &mut Pcg64Mcg) -> FeatureAllocation {
+        let bits: Vec<bool> = (0..n_items * n_features)
+            .map(|_| rng.gen_bool(0.4))
+            .collect();
+        FeatureAllocation::from_fn(n_items, n_features, |i, j| bits[i * n_features + j])
+    }
+
+    #[test]
+    fn solvers_agree_on_loss() {
+        let mut rng = Pcg64Mcg::seed_from_u64(7);
+        for &a in &[0.3, 1.0, 1.7] {
+            for _ in 0..20 {
+                let k1 = rng.gen_range(0..=5);
+                let k2 = rng.gen_range(0..=5);
+                let z1 = random(12, k1, &mut rng);
+                let z2 = random(12, k2, &mut rng);
+                let Some(w) = make_weight_matrix(&z1, &z2, a, Solver::Lapjv) else {
+                    continue;
+                };
+                let losses: Vec<f64> = SOLVERS
+                    .iter()
+                    .map(|solver| {
+                        let state = solver.solve(&w, &Cancellation::default()).unwrap();
+                        let mut in_row = state.in_row().to_vec();
+                        in_row.sort_unstable();
+                        assert_eq!(in_row, (0..w.dim()).collect::<Vec<_>>());
+                        w.cost(&state)
+                    })
+                    .collect();
+                for loss in &losses[1..] {
+                    assert_relative_eq!(*loss, losses[0], max_relative = 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solvers_honor_updates_and_cancellation() {
+        let mut rng = Pcg64Mcg::seed_from_u64(11);
+        let z1 = random(10, 4, &mut rng);
+        let z2 = random(10, 3, &mut rng);
+        let z3 = random(10, 3, &mut rng);
+        let w = make_weight_matrix(&z1, &z3, 0.8, Solver::Lapjv).unwrap();
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        for solver in SOLVERS {
+            let old = make_weight_matrix(&z1, &z2, 0.8, solver).unwrap();
+            let mut state = solver.solve(&old, &Cancellation::default()).unwrap();
+            solver
+                .update(&w, &mut state, &[0, 1, 2, 3], &Cancellation::default())
+                .unwrap();
+            assert_relative_eq!(w.cost(&state), crate::loss::loss(&w), max_relative = 1e-9);
+            assert!(solver.solve(&w, &cancellation).is_none());
+        }
+    }
+
+    #[test]
+    fn exhaustive_solver_refuses_many_features() {
+        use crate::{Error, Fangs, FaroLoss, Samples, Silent};
+        let mut rng = Pcg64Mcg::seed_from_u64(13);
+        let faro = FaroLoss::new(1.0).unwrap().with_solver(Solver::Exhaustive);
+        let max_features = Solver::Exhaustive.max_features();
+        let small = random(4, 3, &mut rng);
+        let large = FeatureAllocation::from_fn(4, max_features + 1, |i, j| (i + j) % 2 == 0);
+        assert!(faro.loss(&small, &random(4, 2, &mut rng)).is_ok());
+        assert_eq!(faro.loss(&small, &large).unwrap_err(), Error::TooLarge);
+        let samples = Samples::new(vec![large.clone(), small]).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        assert_eq!(
+            faro.expected_loss(&random(4, 1, &mut rng), &samples, &pool)
+                .unwrap_err(),
+            Error::TooLarge
+        );
+        let z = random(4, 1, &mut rng);
+        let views = samples.as_slice();
+        assert_eq!(
+            faro.pairwise_losses(views, &pool).unwrap_err(),
+            Error::TooLarge
+        );
+        assert_eq!(
+            faro.pairwise_losses_augmented(views, &pool).unwrap_err(),
+            Error::TooLarge
+        );
+        assert_eq!(faro.medoid(&samples, &pool).unwrap_err(), Error::TooLarge);
+        assert_eq!(
+            faro.credible_ball(&z, &samples, 0.9, &pool).unwrap_err(),
+            Error::TooLarge
+        );
+        assert_eq!(
+            faro.expected_loss_standard_error(&z, views, samples.weights(), &pool)
+                .unwrap_err(),
+            Error::TooLarge
+        );
+        assert_eq!(
+            Fangs::new()
+                .loss(faro)
+                .quiet(true)
+                .run(&samples, &mut Silent)
+                .unwrap_err(),
+            Error::TooLarge
+        );
+        assert!(Fangs::new()
+            .loss(faro.with_solver(Solver::Hungarian))
+            .n_init(2)
+            .n_iterations(10)
+            .quiet(true)
+            .run(&samples, &mut Silent)
+            .is_ok());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{Error, FaroLoss, Samples};
+use rayon::ThreadPool;
+
+impl FaroLoss {
+    /// A lower bound on the smallest expected loss of any feature allocation based on posterior
+    /// `samples`, which is the larger of two bounds.
+    ///
+    /// The first is from the triangle inequality: for any `z`, the loss between samples `s` and
+    /// `t` is at most the sum of the losses between them and `z`, so the expected loss of `z` is
+    /// at least half the mean loss between pairs of samples.  Since the loss is not symmetric
+    /// unless `a` is one, this uses the loss with `a` equal to one, scaled by the smaller of `a`
+    /// and `b`.
+    ///
+    /// The second is from the number of features of each item, which does not depend on the
+    /// order of the features: the loss is at least the sum over items of `a` times the excess
+    /// or `b` times the shortfall of the number of features of the item in the first feature
+    /// allocation relative to the second, and the expected value of this sum is minimized
+    /// item by item.
+    pub fn lower_bound(&self, samples: &Samples, pool: &ThreadPool) -> Result<f64, Error> {
+        Ok(self
+            .triangle_bound(samples, pool)?
+            .max(self.item_bound(samples)))
+    }
+
+    fn triangle_bound(&self, samples: &Samples, pool: &ThreadPool) -> Result<f64, Error> {
+        let losses = FaroLoss::new(1.0)?.pairwise_losses(samples.as_slice(), pool)?;
+        let weights = samples.weights();
+        let total: f64 = weights.iter().sum();
+        let mean = losses
+            .rows()
+            .into_iter()
+            .zip(weights)
+            .map(|(row, w1)| w1 * row.iter().zip(weights).map(|(x, w2)| w2 * x).sum::<f64>())
+            .sum::<f64>()
+            / (total * total);
+        Ok(self.a().min(self.b()) * mean / 2.0)
+    }
+
+    fn item_bound(&self, samples: &Samples) -> f64 {
+        let (a, b) = (self.a(), self.b());
+        let weights = samples.weights();
+        let total: f64 = weights.iter().sum();
+        (0..samples.n_items())
+            .map(|i| {
+                let counts: Vec<usize> = samples
+                    .as_slice()
+                    .iter()
+                    .map(|z| (0..z.n_features()).filter(|&j| z.get(i, j)).count())
+                    .collect();
+                let max_count = counts.iter().copied().max().unwrap_or(0);
+                (0..=max_count)
+                    .map(|count| {
+                        counts
+                            .iter()
+                            .zip(weights)
+                            .map(|(&c, w)| {
+                                w * if count > c {
+                                    a * (count - c) as f64
+                                } else {
+                                    b * (c - count) as f64
+                                }
+                            })
+                            .sum::<f64>()
+                    })
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .sum::<f64>()
+            / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Algorithm, Fangs, FeatureAllocation, Silent};
+
+    #[test]
+    fn lower_bound_is_below_the_minimum() {
+        let samples = Samples::with_weights(
+            vec![
+                FeatureAllocation::from_fn(4, 2, |i, j| i <= j + 1),
+                FeatureAllocation::from_fn(4, 1, |i, _| i % 2 == 0),
+                FeatureAllocation::from_fn(4, 3, |i, j| i == j),
+                FeatureAllocation::from_fn(4, 2, |i, j| i + j == 3),
+            ],
+            vec![1.0, 2.0, 1.0, 0.5],
+        )
+        .unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        for a in [0.5, 1.0, 1.6] {
+            let faro = FaroLoss::new(a).unwrap();
+            let bound = faro.lower_bound(&samples, &pool).unwrap();
+            let minimum = Fangs::new()
+                .loss(faro)
+                .algorithm(Algorithm::Exhaustive)
+                .max_features(3)
+                .run(&samples, &mut Silent)
+                .unwrap()
+                .expected_loss;
+            assert!(bound > 0.0);
+            assert!(bound <= minimum);
+        }
+        // With identical samples, the bound is attained by the sample itself.
+        let z = FeatureAllocation::from_fn(4, 2, |i, j| i <= j);
+        let samples = Samples::new(vec![z.clone(), z]).unwrap();
+        let faro = FaroLoss::new(1.0).unwrap();
+        assert_eq!(faro.lower_bound(&samples, &pool).unwrap(), 0.0);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Identifies a checkpoint file and the version of its format.
+const HEADER: &[u8; 8] = b"FANGS\x00\x00\x06";
+
+/// Writes `state` to `path`.  The file is first written under a temporary name and then renamed,
+/// so that an existing checkpoint is not lost if writing is interrupted.
+pub(crate) fn write<T: Serialize>(path: &Path, state: &T) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp).map_err(|_| Error::Checkpoint)?);
+    writer.write_all(HEADER).map_err(|_| Error::Checkpoint)?;
+    bincode::serialize_into(&mut writer, state).map_err(|_| Error::Checkpoint)?;
+    writer.flush().map_err(|_| Error::Checkpoint)?;
+    drop(writer);
+    std::fs::rename(&tmp, path).map_err(|_| Error::Checkpoint)
+}
+
+pub(crate) fn read<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let mut reader = BufReader::new(File::open(path).map_err(|_| Error::Checkpoint)?);
+    let mut header = [0_u8; HEADER.len()];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| Error::Checkpoint)?;
+    if &header != HEADER {
+        return Err(Error::Checkpoint);
+    }
+    bincode::deserialize_from(reader).map_err(|_| Error::Checkpoint)
+}
+
+/// Serializes the warm-start states of the assignment solver by their parts.
+pub(crate) mod solver_states {
+    use crate::loss::SolverState;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    type Parts = (Vec<f64>, Vec<usize>, Vec<usize>);
+
+    pub(crate) fn serialize<S: Serializer>(
+        states: &[SolverState],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let parts: Vec<_> = states
+            .iter()
+            .map(|x| (x.v(), x.in_row(), x.in_col()))
+            .collect();
+        parts.serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<SolverState>, D::Error> {
+        let parts = Vec::<Parts>::deserialize(deserializer)?;
+        Ok(parts
+            .into_iter()
+            .map(|(v, in_row, in_col)| SolverState::from_parts(v, in_row, in_col))
+            .collect())
+    }
+}
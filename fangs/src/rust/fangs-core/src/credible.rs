@@ -0,0 +1,140 @@
+use crate::loss::{losses_from_samples, Cancellation};
+use crate::{Error, FaroLoss, FeatureAllocation, Samples};
+use rayon::ThreadPool;
+
+/// A credible ball of feature allocations centered at a point estimate, i.e., the smallest ball
+/// in FARO loss containing the samples having at least a given posterior probability, along the
+/// lines of the credible ball for partitions of:
+///
+/// S. Wade, Z. Ghahramani (2018), Bayesian Cluster Analysis: Point Estimation and Credible
+/// Balls, Bayesian Analysis, 13 (2), 559-626.
+///
+/// The ball is summarized by bounds, each being the samples in the ball which are most extreme
+/// in some respect, where ties are broken by the greatest loss from the estimate.
+#[derive(Debug, Clone)]
+pub struct CredibleBall {
+    /// The greatest loss from the estimate of a sample in the ball.
+    pub radius: f64,
+    /// The (weighted) proportion of samples in the ball, which is at least the credible level.
+    pub probability: f64,
+    /// The samples in the ball with the most features.
+    pub upper_vertical: Vec<FeatureAllocation>,
+    /// The samples in the ball with the fewest features.
+    pub lower_vertical: Vec<FeatureAllocation>,
+    /// The samples in the ball with the most ones.
+    pub upper_horizontal: Vec<FeatureAllocation>,
+    /// The samples in the ball with the fewest ones.
+    pub lower_horizontal: Vec<FeatureAllocation>,
+}
+
+impl FaroLoss {
+    /// The credible ball centered at `z` with credible level `level` based on posterior
+    /// `samples`.
+    pub fn credible_ball(
+        &self,
+        z: &FeatureAllocation,
+        samples: &Samples,
+        level: f64,
+        pool: &ThreadPool,
+    ) -> Result<CredibleBall, Error> {
+        if z.n_items() != samples.n_items() {
+            return Err(Error::InconsistentItems);
+        }
+        if !(level > 0.0 && level <= 1.0) {
+            return Err(Error::InvalidLevel);
+        }
+        self.check_size(z.n_features().max(samples.max_n_features()))?;
+        let views = samples.as_slice();
+        let weights = samples.weights();
+        let losses = losses_from_samples(
+            z,
+            views,
+            self.a(),
+            self.solver(),
+            &Cancellation::default(),
+            pool,
+        )
+        .unwrap();
+        let mut order: Vec<usize> = (0..views.len()).collect();
+        order.sort_by(|&k1, &k2| losses[k1].partial_cmp(&losses[k2]).unwrap());
+        let total: f64 = weights.iter().sum();
+        let mut radius = losses[order[order.len() - 1]];
+        let mut cumulative = 0.0;
+        for &k in &order {
+            cumulative += weights[k];
+            if cumulative >= level * total {
+                radius = losses[k];
+                break;
+            }
+        }
+        let ball: Vec<usize> = order.into_iter().filter(|&k| losses[k] <= radius).collect();
+        let probability = ball.iter().map(|&k| weights[k]).sum::<f64>() / total;
+        let bound = |count: fn(&FeatureAllocation) -> usize, most: bool| {
+            let key = |k: usize| {
+                let x = count(&views[k]) as i64;
+                if most {
+                    x
+                } else {
+                    -x
+                }
+            };
+            let best = ball.iter().map(|&k| key(k)).max().unwrap();
+            let farthest = ball
+                .iter()
+                .filter(|&&k| key(k) == best)
+                .map(|&k| losses[k])
+                .fold(f64::NEG_INFINITY, f64::max);
+            ball.iter()
+                .filter(|&&k| key(k) == best && losses[k] == farthest)
+                .map(|&k| views[k].clone())
+                .collect()
+        };
+        let n_ones = |zz: &FeatureAllocation| (0..zz.n_features()).map(|j| zz.count_ones(j)).sum();
+        Ok(CredibleBall {
+            radius,
+            probability,
+            upper_vertical: bound(FeatureAllocation::n_features, true),
+            lower_vertical: bound(FeatureAllocation::n_features, false),
+            upper_horizontal: bound(n_ones, true),
+            lower_horizontal: bound(n_ones, false),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ball_contains_the_requested_probability() {
+        let z = FeatureAllocation::from_fn(4, 2, |i, j| i == j);
+        let near = FeatureAllocation::from_fn(4, 3, |i, j| i == j);
+        let far = FeatureAllocation::from_fn(4, 1, |i, _| i < 2);
+        let farther = FeatureAllocation::from_fn(4, 3, |i, j| i != j);
+        let samples = Samples::with_weights(
+            vec![z.clone(), near.clone(), far.clone(), farther],
+            vec![4.0, 3.0, 2.0, 1.0],
+        )
+        .unwrap();
+        let faro = FaroLoss::new(1.0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let ball = faro.credible_ball(&z, &samples, 0.7, &pool).unwrap();
+        assert_eq!(ball.radius, 1.0);
+        assert_eq!(ball.probability, 0.7);
+        assert_eq!(ball.upper_vertical, vec![near.clone()]);
+        assert_eq!(ball.lower_vertical, vec![z.clone()]);
+        assert_eq!(ball.upper_horizontal, vec![near]);
+        assert_eq!(ball.lower_horizontal, vec![z.clone()]);
+        let ball = faro.credible_ball(&z, &samples, 0.9, &pool).unwrap();
+        assert_eq!(ball.radius, faro.loss(&z, &far).unwrap());
+        assert_eq!(ball.lower_vertical, vec![far.clone()]);
+        assert_eq!(ball.lower_horizontal, vec![far]);
+        assert_eq!(
+            faro.credible_ball(&z, &samples, 0.0, &pool).unwrap_err(),
+            Error::InvalidLevel
+        );
+    }
+}
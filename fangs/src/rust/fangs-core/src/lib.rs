@@ -0,0 +1,84 @@
+//! Feature allocation neighborhood greedy search (FANGS).
+//!
+//! A point estimate of a feature allocation is found by minimizing the expected FARO loss based
+//! on posterior samples, as described in:
+//!
+//! D. B. Dahl, D. J. Johnson, R. J. Andros (2023+), Comparison and Bayesian Estimation of
+//! Feature Allocations, Journal of Computational and Graphical Statistics,
+//! doi:10.1080/10618600.2023.2204136.
+
+mod allocation;
+mod assignment;
+mod bound;
+mod checkpoint;
+mod credible;
+mod loss;
+mod pairwise;
+mod samples;
+mod search;
+mod standard_error;
+mod timers;
+
+pub use allocation::FeatureAllocation;
+pub use assignment::Solver;
+pub use credible::CredibleBall;
+pub use loss::{FaroLoss, WeightMatrix};
+pub use pairwise::medoid;
+pub use samples::Samples;
+pub use search::{
+    Algorithm, Fangs, FangsResult, FangsSearch, Monitor, Schedule, Silent, SweetResult, TracePoint,
+    Truncated,
+};
+pub use standard_error::StandardError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    NoSamples,
+    InconsistentItems,
+    InconsistentDimensions,
+    NotBinary,
+    InvalidCost,
+    InvalidSchedule,
+    InvalidProbability,
+    InvalidWeights,
+    InvalidAlgorithm,
+    InvalidLevel,
+    TooLarge,
+    Checkpoint,
+    CheckpointMismatch,
+    Interrupted,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let msg = match self {
+            Error::NoSamples => "Number of samples must be at least one.",
+            Error::InconsistentItems => {
+                "All feature allocations must have the same number of items."
+            }
+            Error::InconsistentDimensions => "Data length is inconsistent with the dimensions.",
+            Error::NotBinary => "All elements of a feature allocation must be 0 or 1.",
+            Error::InvalidCost => "Cost parameter 'a' must be in (0,2).",
+            Error::InvalidSchedule => {
+                "Temperature must be nonnegative, cooling rate must be in (0,1], and target acceptance rate must be in (0,1)."
+            }
+            Error::InvalidProbability => "Probability of column moves must be in [0,1].",
+            Error::InvalidWeights => {
+                "There must be one finite, nonnegative weight per sample, and not all may be zero."
+            }
+            Error::InvalidAlgorithm => {
+                "Only the stochastic and annealing algorithms can be continued."
+            }
+            Error::InvalidLevel => "Credible level must be in (0,1].",
+            Error::TooLarge => "The problem is too large for the exhaustive algorithm or solver.",
+            Error::Checkpoint => "Cannot read or write the checkpoint file.",
+            Error::CheckpointMismatch => {
+                "The samples are not the same as those of the search that wrote the checkpoint."
+            }
+            Error::Interrupted => "Caught user interrupt before main loop, so aborting.",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,1070 @@
+use crate::{Error, FeatureAllocation, Samples, Solver};
+pub(crate) use lapjv::Cancellation;
+use ndarray::prelude::*;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use serde::{Deserialize, Serialize};
+
+/// The FARO loss, i.e., the generalized Hamming distance between binary matrices minimized over
+/// all permutations of the columns, where the columns are padded with zeros as needed.
+///
+/// The cost of a one in the first feature allocation matched with a zero in the second is `a`,
+/// whereas the cost of a zero matched with a one is `b = 2 - a`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaroLoss {
+    a: f64,
+    solver: Solver,
+}
+
+impl FaroLoss {
+    pub fn new(a: f64) -> Result<Self, Error> {
+        if a <= 0.0 || a >= 2.0 || a.is_nan() {
+            return Err(Error::InvalidCost);
+        }
+        Ok(Self {
+            a,
+            solver: Solver::default(),
+        })
+    }
+
+    /// Uses `solver` for the linear assignment problems defining the loss.
+    pub fn with_solver(mut self, solver: Solver) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn a(&self) -> f64 {
+        self.a
+    }
+
+    pub fn b(&self) -> f64 {
+        2.0 - self.a
+    }
+
+    pub fn solver(&self) -> Solver {
+        self.solver
+    }
+
+    /// The linear assignment problem defining the loss between `z1` and `z2`, or `None` if
+    /// neither has any features.
+    pub fn weight_matrix(
+        &self,
+        z1: &FeatureAllocation,
+        z2: &FeatureAllocation,
+    ) -> Result<Option<WeightMatrix>, Error> {
+        check_items(z1, z2)?;
+        self.check_size(z1.n_features().max(z2.n_features()))?;
+        Ok(make_weight_matrix(z1, z2, self.a, self.solver))
+    }
+
+    pub fn loss(&self, z1: &FeatureAllocation, z2: &FeatureAllocation) -> Result<f64, Error> {
+        Ok(match self.weight_matrix(z1, z2)? {
+            Some(weight_matrix) => loss(&weight_matrix),
+            None => 0.0,
+        })
+    }
+
+    /// The loss together with the optimal column permutations, where element `j` of the first
+    /// permutation is the column of `z2` matched to column `j` of `z1` and vice versa for the
+    /// second permutation.  Columns beyond the number of features are padding columns of zeros.
+    pub fn loss_augmented(
+        &self,
+        z1: &FeatureAllocation,
+        z2: &FeatureAllocation,
+    ) -> Result<(f64, Vec<usize>, Vec<usize>), Error> {
+        Ok(match self.weight_matrix(z1, z2)? {
+            Some(weight_matrix) => weight_matrix.solution(),
+            None => (0.0, vec![], vec![]),
+        })
+    }
+
+    /// For each of `samples`, the column permutation which aligns it to `z`, i.e., the first
+    /// permutation of [`FaroLoss::loss_augmented`].
+    pub fn alignments(
+        &self,
+        z: &FeatureAllocation,
+        samples: &[FeatureAllocation],
+        pool: &ThreadPool,
+    ) -> Result<Vec<Vec<usize>>, Error> {
+        samples.iter().try_for_each(|zz| check_items(z, zz))?;
+        self.check_size(
+            samples
+                .iter()
+                .fold(z.n_features(), |n, zz| n.max(zz.n_features())),
+        )?;
+        Ok(alignments(
+            z,
+            samples,
+            self.a,
+            self.solver,
+            &Cancellation::default(),
+            pool,
+        )
+        .unwrap())
+    }
+
+    /// The aligned elementwise posterior proportions, whose element `(i, j)` is the weighted
+    /// proportion of `samples` in which item `i` has the feature aligned to feature `j` of `z`.
+    pub fn aligned_proportions(
+        &self,
+        z: &FeatureAllocation,
+        samples: &Samples,
+        pool: &ThreadPool,
+    ) -> Result<Array2<f64>, Error> {
+        if z.n_items() != samples.n_items() {
+            return Err(Error::InconsistentItems);
+        }
+        self.check_size(z.n_features().max(samples.max_n_features()))?;
+        let permutations = alignments(
+            z,
+            samples.as_slice(),
+            self.a,
+            self.solver,
+            &Cancellation::default(),
+            pool,
+        )
+        .unwrap();
+        Ok(aligned_proportions(
+            samples.as_slice(),
+            samples.weights(),
+            &permutations,
+            z.n_features(),
+        ))
+    }
+
+    /// The loss computed by brute force over all column permutations, which is useful only to
+    /// check [`FaroLoss::loss`] on small problems.
+    pub fn loss_permutations(
+        &self,
+        z1: &FeatureAllocation,
+        z2: &FeatureAllocation,
+    ) -> Result<f64, Error> {
+        use itertools::Itertools;
+        check_items(z1, z2)?;
+        let a = self.a;
+        let b = self.b();
+        let k = z1.n_features().max(z2.n_features());
+        Ok((0..k)
+            .permutations(k)
+            .map(|permutation| {
+                permutation.iter().enumerate().fold(0.0, |loss, (j1, &j2)| {
+                    loss + column_cost(z1, j1, z2, j2, a, b)
+                })
+            })
+            .reduce(f64::min)
+            .unwrap_or(0.0))
+    }
+
+    /// The Monte Carlo estimate of the expected loss of `z` based on posterior `samples`.
+    pub fn expected_loss(
+        &self,
+        z: &FeatureAllocation,
+        samples: &Samples,
+        pool: &ThreadPool,
+    ) -> Result<f64, Error> {
+        if z.n_items() != samples.n_items() {
+            return Err(Error::InconsistentItems);
+        }
+        self.check_size(z.n_features().max(samples.max_n_features()))?;
+        Ok(expected_loss_from_samples(
+            z,
+            samples.as_slice(),
+            samples.weights(),
+            self.a,
+            self.solver,
+            &Cancellation::default(),
+            pool,
+        )
+        .unwrap())
+    }
+
+    /// Checks that the solver handles feature allocations with `n_features` features.
+    pub(crate) fn check_size(&self, n_features: usize) -> Result<(), Error> {
+        if n_features > self.solver.max_features() {
+            Err(Error::TooLarge)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn check_items(z1: &FeatureAllocation, z2: &FeatureAllocation) -> Result<(), Error> {
+    if z1.n_items() != z2.n_items() {
+        Err(Error::InconsistentItems)
+    } else {
+        Ok(())
+    }
+}
+
+/// The linear assignment problem defining the FARO loss between two feature allocations, whose
+/// features are matched or left unmatched, i.e., matched with a padding column of zeros.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightMatrix {
+    costs: Array2<f64>,
+    unmatched_rows: Vec<f64>,
+    unmatched_cols: Vec<f64>,
+    solver: Solver,
+}
+
+impl WeightMatrix {
+    /// The cost of matching feature `j1` of the first feature allocation with feature `j2` of the
+    /// second, as element `(j1, j2)`.
+    pub fn costs(&self) -> &Array2<f64> {
+        &self.costs
+    }
+
+    /// The cost of leaving each feature of the first feature allocation unmatched.
+    pub fn unmatched_rows(&self) -> &[f64] {
+        &self.unmatched_rows
+    }
+
+    /// The cost of leaving each feature of the second feature allocation unmatched.
+    pub fn unmatched_cols(&self) -> &[f64] {
+        &self.unmatched_cols
+    }
+
+    /// The algorithm which solves the assignment problem.
+    pub fn solver(&self) -> Solver {
+        self.solver
+    }
+
+    /// The number of rows (and columns) of the assignment problem, i.e., the larger number of
+    /// features.
+    pub(crate) fn dim(&self) -> usize {
+        let (k1, k2) = self.costs.dim();
+        k1.max(k2)
+    }
+
+    /// The cost of assigning row `i` to column `j`, where rows and columns beyond those of the
+    /// costs stand for being unmatched.
+    pub(crate) fn entry(&self, i: usize, j: usize) -> f64 {
+        let (k1, k2) = self.costs.dim();
+        match (i < k1, j < k2) {
+            (true, true) => self.costs[[i, j]],
+            (true, false) => self.unmatched_rows[i],
+            (false, true) => self.unmatched_cols[j],
+            (false, false) => 0.0,
+        }
+    }
+
+    pub(crate) fn lapjv(&self, cancellation: &Cancellation) -> lapjv::LapJV<'_, f64> {
+        lapjv::LapJV::rectangular(&self.costs, &self.unmatched_rows, &self.unmatched_cols)
+            .with_cancellation(cancellation.clone())
+    }
+
+    /// The solution of the assignment problem, or `None` if `cancellation` is cancelled first.
+    fn solve(&self, cancellation: &Cancellation) -> Option<SolverState> {
+        self.solver.solve(self, cancellation)
+    }
+
+    /// Re-solves the assignment problem after rows `rows` changed, starting from `state`, which
+    /// is left empty if `cancellation` is cancelled first.
+    fn update(
+        &self,
+        state: &mut SolverState,
+        rows: &[usize],
+        cancellation: &Cancellation,
+    ) -> Option<()> {
+        self.solver.update(self, state, rows, cancellation)
+    }
+
+    /// The loss given the solution of the assignment problem.
+    pub(crate) fn cost(&self, state: &SolverState) -> f64 {
+        lapjv::cost_rectangular(
+            &self.costs,
+            &self.unmatched_rows,
+            &self.unmatched_cols,
+            state.in_row(),
+        )
+    }
+
+    /// The loss and the permutations of [`FaroLoss::loss_augmented`].
+    pub(crate) fn solution(&self) -> (f64, Vec<usize>, Vec<usize>) {
+        let state = self.solve(&Cancellation::default()).unwrap();
+        (
+            self.cost(&state),
+            state.in_row().to_vec(),
+            state.in_col().to_vec(),
+        )
+    }
+}
+
+/// The weight matrices between `z` and each of `samples`, or `None` if `cancellation` is
+/// cancelled first.  A sample without features gives an empty weight matrix if `z` has none.
+pub(crate) fn make_weight_matrices(
+    z: &FeatureAllocation,
+    samples: &[FeatureAllocation],
+    a: f64,
+    solver: Solver,
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<WeightMatrix>> {
+    pool.install(|| {
+        samples
+            .par_iter()
+            .map(|zz| (!cancellation.is_cancelled()).then(|| weight_matrix(z, zz, a, solver)))
+            .collect()
+    })
+}
+
+pub(crate) fn flip_bit(
+    z: &mut FeatureAllocation,
+    matrices: &mut [WeightMatrix],
+    a: f64,
+    index: [usize; 2],
+    samples: &[FeatureAllocation],
+) {
+    let [i0, i1] = index;
+    let old_bit = z.get(i0, i1);
+    z.flip(i0, i1);
+    let b = 2.0 - a;
+    samples.iter().zip(matrices.iter_mut()).for_each(|(zz, w)| {
+        update_w(zz, w, i0, i1, a, b, old_bit);
+    });
+    /*
+    // Sanity check, but commented out for speed.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    approx::assert_ulps_eq!(
+        expected_loss_from_samples(z, samples, &vec![1.0; samples.len()], a, &pool),
+        expected_loss_from_weight_matrices(matrices, &pool),
+        max_ulps = 4
+    );
+    */
+}
+
+/// Adds a feature to `z`, where `column` indicates whether each item has it, and updates the
+/// weight matrices to match.
+pub(crate) fn push_column(
+    z: &mut FeatureAllocation,
+    matrices: &mut [WeightMatrix],
+    a: f64,
+    column: &[bool],
+    samples: &[FeatureAllocation],
+) {
+    let b = 2.0 - a;
+    let j = z.n_features();
+    z.push_column(column);
+    let unmatched = a * z.count_ones(j) as f64;
+    samples.iter().zip(matrices.iter_mut()).for_each(|(zz, w)| {
+        let row = Array1::from_shape_fn(zz.n_features(), |i2| column_cost(z, j, zz, i2, a, b));
+        w.costs.push_row(row.view()).unwrap();
+        w.unmatched_rows.push(unmatched);
+    });
+}
+
+/// Removes feature `j` from `z` (replacing it with the last feature) and updates the weight
+/// matrices to match.
+pub(crate) fn swap_remove_column(
+    z: &mut FeatureAllocation,
+    matrices: &mut [WeightMatrix],
+    j: usize,
+) {
+    let last = z.n_features() - 1;
+    matrices.iter_mut().for_each(|w| {
+        if j != last {
+            let (mut x, y) = w.costs.multi_slice_mut((s![j, ..], s![last, ..]));
+            x.assign(&y);
+        }
+        w.costs = w.costs.slice(s![..last, ..]).to_owned();
+        w.unmatched_rows.swap_remove(j);
+    });
+    z.swap_remove_column(j);
+}
+
+fn update_w(
+    zz: &FeatureAllocation,
+    w: &mut WeightMatrix,
+    i0: usize,
+    i1: usize,
+    a: f64,
+    b: f64,
+    bit: bool,
+) {
+    // The changes for item `i0` not having and having the feature in the sample.
+    let (if_zero, if_one) = if !bit { (a, -b) } else { (-a, b) };
+    let (word, mask) = FeatureAllocation::bit_position(i0);
+    let mut row = w.costs.row_mut(i1);
+    for (i2, x) in row.iter_mut().enumerate() {
+        let bit_in_sample = zz.column_bits(i2)[word] & mask != 0;
+        *x += if bit_in_sample { if_one } else { if_zero };
+    }
+    w.unmatched_rows[i1] += if_zero;
+}
+
+/// The solution of the linear assignment problem for a weight matrix, which is warm-started when
+/// a row of the weight matrix changes.
+pub(crate) type SolverState = lapjv::LapJVState<f64>;
+
+/// The solver states of the weight matrices, or `None` if `cancellation` is cancelled first.
+pub(crate) fn make_solver_states(
+    weight_matrices: &[WeightMatrix],
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<SolverState>> {
+    pool.install(|| {
+        weight_matrices
+            .par_iter()
+            .map(|w| w.solve(cancellation))
+            .collect()
+    })
+}
+
+/// The solver states after row `row` of every weight matrix changed, re-solving from `states`,
+/// or `None` if `cancellation` is cancelled first.
+pub(crate) fn update_solver_states(
+    weight_matrices: &[WeightMatrix],
+    states: &[SolverState],
+    row: usize,
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<SolverState>> {
+    pool.install(|| {
+        weight_matrices
+            .par_iter()
+            .zip(states.par_iter())
+            .map(|(w, state)| {
+                let mut state = state.clone();
+                w.update(&mut state, &[row], cancellation)?;
+                Some(state)
+            })
+            .collect()
+    })
+}
+
+/// Brings the solver states of the samples in `batch` up to date, where `stale` gives, for each
+/// sample, the rows of its weight matrix which changed since its solver state was last updated.
+/// Nothing changes and the result is `None` if `cancellation` is cancelled first.
+pub(crate) fn refresh_solver_states(
+    weight_matrices: &[WeightMatrix],
+    states: &mut [SolverState],
+    stale: &mut [Vec<usize>],
+    batch: &[usize],
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<()> {
+    let refreshed: Vec<_> = pool.install(|| {
+        batch
+            .par_iter()
+            .filter(|&&k| !stale[k].is_empty())
+            .map(|&k| {
+                let mut state = states[k].clone();
+                weight_matrices[k].update(&mut state, &stale[k], cancellation)?;
+                Some((k, state))
+            })
+            .collect::<Option<_>>()
+    })?;
+    for (k, state) in refreshed {
+        states[k] = state;
+        stale[k].clear();
+    }
+    Some(())
+}
+
+/// The solver states of the samples in `batch`, solving from scratch, or `None` if
+/// `cancellation` is cancelled first.
+pub(crate) fn make_solver_states_on_batch(
+    weight_matrices: &[WeightMatrix],
+    batch: &[usize],
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<SolverState>> {
+    pool.install(|| {
+        batch
+            .par_iter()
+            .map(|&k| weight_matrices[k].solve(cancellation))
+            .collect()
+    })
+}
+
+/// The solver states of the samples in `batch` after row `row` of their weight matrices changed,
+/// re-solving from `states`, or `None` if `cancellation` is cancelled first.
+pub(crate) fn update_solver_states_on_batch(
+    weight_matrices: &[WeightMatrix],
+    states: &[SolverState],
+    row: usize,
+    batch: &[usize],
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<SolverState>> {
+    pool.install(|| {
+        batch
+            .par_iter()
+            .map(|&k| {
+                let mut state = states[k].clone();
+                weight_matrices[k].update(&mut state, &[row], cancellation)?;
+                Some(state)
+            })
+            .collect()
+    })
+}
+
+/// The weighted mean over the samples in `batch` of their losses, given their solver states.
+pub(crate) fn expected_loss_on_batch(
+    weight_matrices: &[WeightMatrix],
+    states: &[&SolverState],
+    weights: &[f64],
+    batch: &[usize],
+) -> f64 {
+    let batch_weights: Vec<f64> = batch.iter().map(|&k| weights[k]).collect();
+    mean(
+        batch
+            .iter()
+            .zip(states)
+            .map(|(&k, state)| weight_matrices[k].cost(state))
+            .collect(),
+        &batch_weights,
+    )
+}
+
+pub(crate) fn expected_loss_from_solver_states(
+    weight_matrices: &[WeightMatrix],
+    states: &[SolverState],
+    weights: &[f64],
+) -> f64 {
+    mean(
+        weight_matrices
+            .iter()
+            .zip(states)
+            .map(|(w, state)| w.cost(state))
+            .collect(),
+        weights,
+    )
+}
+
+/// The expected loss if element `index` of `z` were flipped, or `None` if `cancellation` is
+/// cancelled first.  The weight matrices are unchanged either way.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn expected_loss_from_weight_matrices_if_flip_bit(
+    z: &FeatureAllocation,
+    matrices: &mut [WeightMatrix],
+    states: &[SolverState],
+    a: f64,
+    index: [usize; 2],
+    samples: &[FeatureAllocation],
+    weights: &[f64],
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<f64> {
+    let [i0, i1] = index;
+    let old_bit = z.get(i0, i1);
+    let new_bit = !old_bit;
+    let b = 2.0 - a;
+    let losses = pool.install(|| {
+        samples
+            .par_iter()
+            .zip(matrices.par_iter_mut())
+            .zip(states.par_iter())
+            .map(|((zz, w), state)| {
+                if cancellation.is_cancelled() {
+                    return None;
+                }
+                update_w(zz, w, i0, i1, a, b, old_bit);
+                let mut state = state.clone();
+                let lss = w
+                    .update(&mut state, &[i1], cancellation)
+                    .map(|()| w.cost(&state));
+                update_w(zz, w, i0, i1, a, b, new_bit);
+                lss
+            })
+            .collect::<Option<Vec<_>>>()
+    })?;
+    Some(mean(losses, weights))
+}
+
+pub(crate) fn make_weight_matrix(
+    z1: &FeatureAllocation,
+    z2: &FeatureAllocation,
+    a: f64,
+    solver: Solver,
+) -> Option<WeightMatrix> {
+    (z1.n_features() > 0 || z2.n_features() > 0).then(|| weight_matrix(z1, z2, a, solver))
+}
+
+/// Like [`make_weight_matrix`], except that the weight matrix is empty if neither has any
+/// features, so that features can still be added to `z1` later.
+fn weight_matrix(
+    z1: &FeatureAllocation,
+    z2: &FeatureAllocation,
+    a: f64,
+    solver: Solver,
+) -> WeightMatrix {
+    let b = 2.0 - a;
+    let (k1, k2) = (z1.n_features(), z2.n_features());
+    WeightMatrix {
+        costs: Array2::from_shape_fn((k1, k2), |(j1, j2)| column_cost(z1, j1, z2, j2, a, b)),
+        unmatched_rows: (0..k1).map(|j1| a * z1.count_ones(j1) as f64).collect(),
+        unmatched_cols: (0..k2).map(|j2| b * z2.count_ones(j2) as f64).collect(),
+        solver,
+    }
+}
+
+/// The generalized Hamming distance between feature `j1` of `z1` and feature `j2` of `z2`, where
+/// features beyond the number of features are padding columns of zeros.
+fn column_cost(
+    z1: &FeatureAllocation,
+    j1: usize,
+    z2: &FeatureAllocation,
+    j2: usize,
+    a: f64,
+    b: f64,
+) -> f64 {
+    let (n10, n01) = match (j1 < z1.n_features(), j2 < z2.n_features()) {
+        (true, true) => z1
+            .column_bits(j1)
+            .iter()
+            .zip(z2.column_bits(j2))
+            .fold((0, 0), |(n10, n01), (&x1, &x2)| {
+                (n10 + (x1 & !x2).count_ones(), n01 + (!x1 & x2).count_ones())
+            }),
+        (true, false) => (z1.count_ones(j1) as u32, 0),
+        (false, true) => (0, z2.count_ones(j2) as u32),
+        (false, false) => (0, 0),
+    };
+    a * n10 as f64 + b * n01 as f64
+}
+
+/// The expected loss of `z`, or `None` if `cancellation` is cancelled first.
+pub(crate) fn expected_loss_from_samples(
+    z: &FeatureAllocation,
+    samples: &[FeatureAllocation],
+    weights: &[f64],
+    a: f64,
+    solver: Solver,
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<f64> {
+    losses_from_samples(z, samples, a, solver, cancellation, pool)
+        .map(|losses| mean(losses, weights))
+}
+
+/// The loss between `z` and each of `samples`, or `None` if `cancellation` is cancelled first.
+pub(crate) fn losses_from_samples(
+    z: &FeatureAllocation,
+    samples: &[FeatureAllocation],
+    a: f64,
+    solver: Solver,
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<f64>> {
+    pool.install(|| {
+        samples
+            .par_iter()
+            .map(|zz| match make_weight_matrix(z, zz, a, solver) {
+                Some(weight_matrix) => weight_matrix
+                    .solve(cancellation)
+                    .map(|state| weight_matrix.cost(&state)),
+                None => Some(0.0),
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn expected_loss_from_weight_matrices(
+    weight_matrices: &[WeightMatrix],
+    pool: &ThreadPool,
+) -> f64 {
+    let weights = vec![1.0; weight_matrices.len()];
+    mean(
+        pool.install(|| weight_matrices.par_iter().map(loss).collect()),
+        &weights,
+    )
+}
+
+/// For each sample, element `j` of its permutation is the column of the sample matched to
+/// column `j` of `z`, where columns beyond the number of features are padding columns of zeros.
+/// The result is `None` if `cancellation` is cancelled first.
+pub(crate) fn alignments(
+    z: &FeatureAllocation,
+    samples: &[FeatureAllocation],
+    a: f64,
+    solver: Solver,
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+) -> Option<Vec<Vec<usize>>> {
+    pool.install(|| {
+        samples
+            .par_iter()
+            .map(|zz| match make_weight_matrix(z, zz, a, solver) {
+                Some(weight_matrix) => weight_matrix
+                    .solve(cancellation)
+                    .map(|state| state.in_row().to_vec()),
+                None => Some(Vec::new()),
+            })
+            .collect()
+    })
+}
+
+/// The weighted proportions of the samples, aligned by `permutations`, having each of the first
+/// `n_features` features.  The sum is in order so that the result does not depend on the number
+/// of threads.
+pub(crate) fn aligned_proportions(
+    samples: &[FeatureAllocation],
+    weights: &[f64],
+    permutations: &[Vec<usize>],
+    n_features: usize,
+) -> Array2<f64> {
+    let n_items = samples.first().map(|zz| zz.n_items()).unwrap_or(0);
+    let mut sums = Array2::zeros((n_items, n_features));
+    for ((zz, &w), permutation) in samples.iter().zip(weights).zip(permutations) {
+        for (j, &jj) in permutation.iter().take(n_features).enumerate() {
+            if jj < zz.n_features() {
+                for i in 0..n_items {
+                    if zz.get(i, jj) {
+                        sums[[i, j]] += w;
+                    }
+                }
+            }
+        }
+    }
+    sums / weights.iter().sum::<f64>()
+}
+
+/// The weighted mean, summing in order so that the result does not depend on the number of
+/// threads.
+fn mean(losses: Vec<f64>, weights: &[f64]) -> f64 {
+    let (sum, total) = losses
+        .iter()
+        .zip(weights)
+        .fold((0.0, 0.0), |(sum, total), (x, w)| (sum + w * x, total + w));
+    sum / total
+}
+
+pub(crate) fn loss(weight_matrix: &WeightMatrix) -> f64 {
+    weight_matrix.cost(&weight_matrix.solve(&Cancellation::default()).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_ulps_eq;
+
+    fn from_rows(rows: &[&[u8]]) -> FeatureAllocation {
+        let n_features = rows.first().map(|x| x.len()).unwrap_or(0);
+        FeatureAllocation::from_fn(rows.len(), n_features, |i, j| rows[i][j] == 1)
+    }
+
+    #[test]
+    fn loss_agrees_with_permutations() {
+        let z1 = from_rows(&[&[0, 1], &[1, 0], &[1, 1], &[0, 1], &[1, 1], &[1, 1]]);
+        let z2 = from_rows(&[
+            &[0, 0, 1],
+            &[0, 0, 0],
+            &[0, 0, 0],
+            &[0, 0, 0],
+            &[1, 1, 1],
+            &[0, 1, 0],
+        ]);
+        for a in [0.5, 1.0, 1.7] {
+            let faro = FaroLoss::new(a).unwrap();
+            assert_ulps_eq!(
+                faro.loss(&z1, &z2).unwrap(),
+                faro.loss_permutations(&z1, &z2).unwrap()
+            );
+            assert_ulps_eq!(
+                faro.loss(&z2, &z1).unwrap(),
+                faro.loss_permutations(&z2, &z1).unwrap()
+            );
+        }
+        // The weight matrix is not padded, since unmatched features have their own costs.
+        let w = FaroLoss::new(0.5)
+            .unwrap()
+            .weight_matrix(&z1, &z2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(w.costs().dim(), (2, 3));
+        assert_eq!(w.unmatched_rows(), [2.0, 2.5]);
+        assert_eq!(w.unmatched_cols(), [1.5, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn expected_loss_is_weighted_mean() {
+        let z = from_rows(&[&[0, 1], &[1, 0], &[1, 1]]);
+        let z1 = from_rows(&[&[1], &[1], &[0]]);
+        let z2 = from_rows(&[&[0, 0], &[1, 1], &[1, 0]]);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let faro = FaroLoss::new(0.8).unwrap();
+        let samples = Samples::with_weights(
+            vec![z1.clone(), z2.clone(), z1.clone()],
+            vec![0.5, 3.0, 0.5],
+        )
+        .unwrap();
+        assert_ulps_eq!(
+            faro.expected_loss(&z, &samples, &pool).unwrap(),
+            (faro.loss(&z, &z1).unwrap() + 3.0 * faro.loss(&z, &z2).unwrap()) / 4.0
+        );
+    }
+
+    #[test]
+    fn aligned_proportions_agree_with_loss_augmented() {
+        let z = from_rows(&[&[0, 1], &[1, 0], &[1, 1]]);
+        let z1 = from_rows(&[&[1], &[0], &[1]]);
+        let z2 = from_rows(&[&[1, 0, 0], &[0, 1, 0], &[1, 1, 1]]);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let faro = FaroLoss::new(1.0).unwrap();
+        let samples = [z1, z2];
+        let permutations = faro.alignments(&z, &samples, &pool).unwrap();
+        for (zz, permutation) in samples.iter().zip(&permutations) {
+            assert_eq!(permutation, &faro.loss_augmented(&z, zz).unwrap().1);
+        }
+        assert_eq!(permutations[1][..2], [1, 0]);
+        let weighted = Samples::with_weights(samples.to_vec(), vec![1.0, 3.0]).unwrap();
+        let proportions = faro.aligned_proportions(&z, &weighted, &pool).unwrap();
+        assert_eq!(proportions, array![[0.0, 1.0], [0.75, 0.0], [0.75, 1.0]]);
+    }
+
+    #[test]
+    fn flip_bit_updates_weight_matrices() {
+        let z = from_rows(&[&[0, 1], &[1, 0], &[1, 1]]);
+        let samples = vec![
+            from_rows(&[&[1], &[0], &[1]]),
+            from_rows(&[&[1, 0, 0], &[0, 1, 1], &[1, 1, 0]]),
+        ];
+        let a = 0.8;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let mut z_flipped = z.clone();
+        let mut matrices = make_weight_matrices(
+            &z,
+            &samples,
+            a,
+            Solver::default(),
+            &Cancellation::default(),
+            &pool,
+        )
+        .unwrap();
+        flip_bit(&mut z_flipped, &mut matrices, a, [2, 0], &samples);
+        assert!(!z_flipped.get(2, 0));
+        assert_ulps_eq!(
+            expected_loss_from_weight_matrices(&matrices, &pool),
+            expected_loss_from_samples(
+                &z_flipped,
+                &samples,
+                &[1.0, 1.0],
+                a,
+                Solver::default(),
+                &Cancellation::default(),
+                &pool
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn cancelled_computations_give_nothing() {
+        let z = from_rows(&[&[0, 1], &[1, 0], &[1, 1]]);
+        let samples = vec![
+            from_rows(&[&[1], &[0], &[1]]),
+            from_rows(&[&[1, 0, 0], &[0, 1, 1], &[1, 1, 0]]),
+        ];
+        let a = 0.8;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let mut matrices = make_weight_matrices(
+            &z,
+            &samples,
+            a,
+            Solver::default(),
+            &Cancellation::default(),
+            &pool,
+        )
+        .unwrap();
+        let states = make_solver_states(&matrices, &Cancellation::default(), &pool).unwrap();
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        assert!(
+            make_weight_matrices(&z, &samples, a, Solver::default(), &cancellation, &pool)
+                .is_none()
+        );
+        assert!(make_solver_states(&matrices, &cancellation, &pool).is_none());
+        assert!(alignments(&z, &samples, a, Solver::default(), &cancellation, &pool).is_none());
+        assert!(expected_loss_from_samples(
+            &z,
+            &samples,
+            &[1.0, 1.0],
+            a,
+            Solver::default(),
+            &cancellation,
+            &pool
+        )
+        .is_none());
+        let snapshot = matrices.clone();
+        assert!(expected_loss_from_weight_matrices_if_flip_bit(
+            &z,
+            &mut matrices,
+            &states,
+            a,
+            [2, 0],
+            &samples,
+            &[1.0, 1.0],
+            &cancellation,
+            &pool,
+        )
+        .is_none());
+        assert_eq!(matrices, snapshot);
+        assert!(update_solver_states(&matrices, &states, 0, &cancellation, &pool).is_none());
+        assert!(make_solver_states_on_batch(&matrices, &[1], &cancellation, &pool).is_none());
+        assert!(
+            update_solver_states_on_batch(&matrices, &states, 0, &[1], &cancellation, &pool)
+                .is_none()
+        );
+        let mut refreshed = states.clone();
+        let mut stale = vec![vec![0], vec![0, 2]];
+        assert!(refresh_solver_states(
+            &matrices,
+            &mut refreshed,
+            &mut stale,
+            &[0, 1],
+            &cancellation,
+            &pool
+        )
+        .is_none());
+        assert_eq!(stale, [vec![0], vec![0, 2]]);
+    }
+
+    #[test]
+    fn warm_started_solver_states_agree_with_cold_solves() {
+        let samples = vec![
+            from_rows(&[&[1], &[0], &[1], &[1]]),
+            from_rows(&[&[1, 0, 0], &[0, 1, 1], &[1, 1, 0], &[0, 0, 1]]),
+            from_rows(&[&[1, 1], &[0, 1], &[0, 0], &[1, 1]]),
+        ];
+        let weights = [2.0, 1.0, 3.0];
+        let a = 1.3;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let mut z = from_rows(&[&[0, 1, 0], &[1, 0, 0], &[1, 1, 1], &[0, 0, 1]]);
+        let mut matrices = make_weight_matrices(
+            &z,
+            &samples,
+            a,
+            Solver::default(),
+            &Cancellation::default(),
+            &pool,
+        )
+        .unwrap();
+        let mut states = make_solver_states(&matrices, &Cancellation::default(), &pool).unwrap();
+        for step in 0..24 {
+            let index = [step % 4, (step * 7) % 3];
+            let candidate = expected_loss_from_weight_matrices_if_flip_bit(
+                &z,
+                &mut matrices,
+                &states,
+                a,
+                index,
+                &samples,
+                &weights,
+                &Cancellation::default(),
+                &pool,
+            )
+            .unwrap();
+            flip_bit(&mut z, &mut matrices, a, index, &samples);
+            states = update_solver_states(
+                &matrices,
+                &states,
+                index[1],
+                &Cancellation::default(),
+                &pool,
+            )
+            .unwrap();
+            let cold = expected_loss_from_samples(
+                &z,
+                &samples,
+                &weights,
+                a,
+                Solver::default(),
+                &Cancellation::default(),
+                &pool,
+            )
+            .unwrap();
+            assert_ulps_eq!(candidate, cold);
+            assert_ulps_eq!(
+                expected_loss_from_solver_states(&matrices, &states, &weights),
+                cold
+            );
+        }
+    }
+
+    #[test]
+    fn column_moves_update_weight_matrices() {
+        let samples = vec![
+            from_rows(&[&[1], &[0], &[1]]),
+            from_rows(&[&[1, 0, 0], &[0, 1, 1], &[1, 1, 0]]),
+            from_rows(&[&[1, 1], &[0, 1], &[0, 0]]),
+        ];
+        let a = 0.7;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let check = |z: &FeatureAllocation, matrices: &[WeightMatrix]| {
+            let near = |x: &f64, y: &f64| (x - y).abs() < 1e-12;
+            for (w, expected) in matrices.iter().zip(
+                make_weight_matrices(
+                    z,
+                    &samples,
+                    a,
+                    Solver::default(),
+                    &Cancellation::default(),
+                    &pool,
+                )
+                .unwrap(),
+            ) {
+                assert_eq!(w.costs.dim(), expected.costs.dim());
+                assert!(w.costs.iter().zip(&expected.costs).all(|(x, y)| near(x, y)));
+                assert!(w
+                    .unmatched_rows
+                    .iter()
+                    .zip(&expected.unmatched_rows)
+                    .all(|(x, y)| near(x, y)));
+                assert_eq!(w.unmatched_cols, expected.unmatched_cols);
+            }
+        };
+        let mut z = from_rows(&[&[0, 1], &[1, 0], &[1, 1]]);
+        let mut matrices = make_weight_matrices(
+            &z,
+            &samples,
+            a,
+            Solver::default(),
+            &Cancellation::default(),
+            &pool,
+        )
+        .unwrap();
+        push_column(&mut z, &mut matrices, a, &[true, false, true], &samples);
+        check(&z, &matrices);
+        push_column(&mut z, &mut matrices, a, &[false, true, true], &samples);
+        assert_eq!(z.n_features(), 4);
+        check(&z, &matrices);
+        swap_remove_column(&mut z, &mut matrices, 0);
+        check(&z, &matrices);
+        swap_remove_column(&mut z, &mut matrices, 2);
+        swap_remove_column(&mut z, &mut matrices, 0);
+        assert_eq!(z, from_rows(&[&[1], &[0], &[1]]));
+        check(&z, &matrices);
+    }
+
+    #[test]
+    fn invalid_inputs() {
+        assert_eq!(FaroLoss::new(2.0), Err(Error::InvalidCost));
+        let faro = FaroLoss::new(1.0).unwrap();
+        let z1 = FeatureAllocation::zeros(3, 1);
+        let z2 = FeatureAllocation::zeros(4, 1);
+        assert_eq!(faro.loss(&z1, &z2), Err(Error::InconsistentItems));
+        assert_eq!(faro.loss(&z1, &FeatureAllocation::zeros(3, 0)), Ok(0.0));
+    }
+}
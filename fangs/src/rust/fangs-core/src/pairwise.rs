@@ -0,0 +1,204 @@
+use crate::loss::make_weight_matrix;
+use crate::{Error, FaroLoss, FeatureAllocation, Samples, Solver};
+use ndarray::prelude::*;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+impl FaroLoss {
+    /// The matrix of losses between every pair of `samples`, e.g., for multidimensional scaling
+    /// or clustering of the samples, where element `(k1, k2)` is the loss between samples `k1`
+    /// and `k2` in that order.  The matrix is symmetric only if `a` is one.
+    pub fn pairwise_losses(
+        &self,
+        samples: &[FeatureAllocation],
+        pool: &ThreadPool,
+    ) -> Result<Array2<f64>, Error> {
+        check_samples(samples)?;
+        self.check_size(samples.iter().fold(0, |n, z| n.max(z.n_features())))?;
+        let n = samples.len();
+        let mut losses = Array2::zeros((n, n));
+        for ((k1, k2), (loss, _)) in pairs(samples, self.a(), self.solver(), false, pool) {
+            losses[[k1, k2]] = loss;
+        }
+        Ok(losses)
+    }
+
+    /// The matrix of losses between every pair of `samples` together with optimal column
+    /// permutations, where element `(k1, k2)` is a permutation aligning sample `k2` to sample
+    /// `k1`, as in the first permutation of [`FaroLoss::loss_augmented`].
+    pub fn pairwise_losses_augmented(
+        &self,
+        samples: &[FeatureAllocation],
+        pool: &ThreadPool,
+    ) -> Result<(Array2<f64>, Array2<Vec<usize>>), Error> {
+        check_samples(samples)?;
+        self.check_size(samples.iter().fold(0, |n, z| n.max(z.n_features())))?;
+        let n = samples.len();
+        let mut losses = Array2::zeros((n, n));
+        let mut permutations = Array2::from_shape_fn((n, n), |(k1, k2)| {
+            if k1 == k2 {
+                (0..samples[k1].n_features()).collect()
+            } else {
+                Vec::new()
+            }
+        });
+        for ((k1, k2), (loss, permutation)) in pairs(samples, self.a(), self.solver(), true, pool) {
+            losses[[k1, k2]] = loss;
+            permutations[[k1, k2]] = permutation;
+        }
+        Ok((losses, permutations))
+    }
+
+    /// The medoid of the samples, i.e., the sample whose (weighted) mean loss to the samples is
+    /// smallest, along with that mean loss, which is its expected loss.
+    pub fn medoid(
+        &self,
+        samples: &Samples,
+        pool: &ThreadPool,
+    ) -> Result<(FeatureAllocation, f64), Error> {
+        self.check_size(samples.max_n_features())?;
+        let losses = self.pairwise_losses(samples.as_slice(), pool)?;
+        let (k, expected_loss) = medoid(&losses, samples.weights());
+        Ok((samples.as_slice()[k].clone(), expected_loss))
+    }
+}
+
+/// The index of the row of `losses` having the smallest weighted mean, and that mean, where the
+/// first is chosen in case of ties.
+pub fn medoid(losses: &Array2<f64>, weights: &[f64]) -> (usize, f64) {
+    let total: f64 = weights.iter().sum();
+    losses
+        .rows()
+        .into_iter()
+        .map(|row| row.iter().zip(weights).map(|(x, w)| w * x).sum::<f64>() / total)
+        .enumerate()
+        .fold(
+            (0, f64::INFINITY),
+            |best, (k, x)| {
+                if x < best.1 {
+                    (k, x)
+                } else {
+                    best
+                }
+            },
+        )
+}
+
+fn check_samples(samples: &[FeatureAllocation]) -> Result<(), Error> {
+    match samples.first() {
+        None => Err(Error::NoSamples),
+        Some(z) if samples.iter().any(|zz| zz.n_items() != z.n_items()) => {
+            Err(Error::InconsistentItems)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The indices of two samples.
+type Pair = (usize, usize);
+
+/// The loss (and, if `augmented`, the first permutation of [`FaroLoss::loss_augmented`]) for
+/// every pair of distinct samples in both orders.  Each pair in the upper triangle is a task,
+/// which solves one assignment problem when the loss is symmetric and otherwise two.  The
+/// permutations always come from solving both, since the transposed solution may differ from
+/// that of [`FaroLoss::loss_augmented`] when there are ties.
+fn pairs(
+    samples: &[FeatureAllocation],
+    a: f64,
+    solver: Solver,
+    augmented: bool,
+    pool: &ThreadPool,
+) -> Vec<(Pair, (f64, Vec<usize>))> {
+    let solve = |z1: &FeatureAllocation, z2: &FeatureAllocation| match make_weight_matrix(
+        z1, z2, a, solver,
+    ) {
+        Some(weight_matrix) => weight_matrix.solution(),
+        None => (0.0, Vec::new(), Vec::new()),
+    };
+    let n = samples.len();
+    let indices: Vec<_> = (0..n)
+        .flat_map(|k1| (k1 + 1..n).map(move |k2| (k1, k2)))
+        .collect();
+    pool.install(|| {
+        indices
+            .into_par_iter()
+            .flat_map_iter(|(k1, k2)| {
+                let (loss, permutation, _) = solve(&samples[k1], &samples[k2]);
+                let (reverse_loss, reverse_permutation) = if a == 1.0 && !augmented {
+                    (loss, Vec::new())
+                } else {
+                    let (loss, permutation, _) = solve(&samples[k2], &samples[k1]);
+                    (loss, permutation)
+                };
+                let keep =
+                    |permutation: Vec<usize>| if augmented { permutation } else { Vec::new() };
+                [
+                    ((k1, k2), (loss, keep(permutation))),
+                    ((k2, k1), (reverse_loss, keep(reverse_permutation))),
+                ]
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairwise_losses_agree_with_loss() {
+        let samples = vec![
+            FeatureAllocation::from_fn(5, 2, |i, j| i > j),
+            FeatureAllocation::from_fn(5, 3, |i, j| (i + j) % 2 == 0),
+            FeatureAllocation::zeros(5, 0),
+            FeatureAllocation::from_fn(5, 1, |i, _| i < 3),
+        ];
+        let faro = FaroLoss::new(0.8).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let (losses, permutations) = faro.pairwise_losses_augmented(&samples, &pool).unwrap();
+        assert_eq!(losses, faro.pairwise_losses(&samples, &pool).unwrap());
+        for (k1, z1) in samples.iter().enumerate() {
+            assert_eq!(losses[[k1, k1]], 0.0);
+            for (k2, z2) in samples.iter().enumerate().skip(k1 + 1) {
+                let (loss, permutation, _) = faro.loss_augmented(z1, z2).unwrap();
+                assert_eq!(losses[[k1, k2]], loss);
+                assert_eq!(permutations[[k1, k2]], permutation);
+                let (loss, permutation, _) = faro.loss_augmented(z2, z1).unwrap();
+                assert_eq!(losses[[k2, k1]], loss);
+                assert_eq!(permutations[[k2, k1]], permutation);
+            }
+        }
+        let hamming = FaroLoss::new(1.0).unwrap();
+        let (losses, permutations) = hamming.pairwise_losses_augmented(&samples, &pool).unwrap();
+        assert_eq!(losses, hamming.pairwise_losses(&samples, &pool).unwrap());
+        assert_eq!(losses, losses.t());
+        for (k1, z1) in samples.iter().enumerate() {
+            for (k2, z2) in samples.iter().enumerate().filter(|&(k2, _)| k2 != k1) {
+                let (loss, permutation, _) = hamming.loss_augmented(z1, z2).unwrap();
+                assert_eq!(losses[[k1, k2]], loss);
+                assert_eq!(permutations[[k1, k2]], permutation);
+            }
+        }
+        assert_eq!(
+            losses[[0, 1]],
+            hamming.loss(&samples[1], &samples[0]).unwrap()
+        );
+        let samples = Samples::new(samples).unwrap();
+        let (z, expected_loss) = faro.medoid(&samples, &pool).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        assert_eq!(z, *samples.get(3).unwrap());
+        approx::assert_ulps_eq!(
+            expected_loss,
+            faro.expected_loss(&z, &samples, &pool).unwrap()
+        );
+        for (zz, _) in samples.iter() {
+            assert!(faro.expected_loss(zz, &samples, &pool).unwrap() >= expected_loss);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{Error, FeatureAllocation};
+use std::collections::HashMap;
+
+/// A nonempty collection of posterior samples of feature allocations, all having the same
+/// number of items.
+///
+/// Since the FARO loss does not depend on the order of the features or on features having no
+/// items, each sample is put in left-ordered form without empty features.  Identical samples are
+/// then stored once, along with the number of times they occur.
+#[derive(Debug, Clone)]
+pub struct Samples {
+    samples: Vec<FeatureAllocation>,
+    counts: Vec<usize>,
+    weights: Vec<f64>,
+    n_samples: usize,
+    n_items: usize,
+    max_n_features: usize,
+}
+
+impl Samples {
+    pub fn new(samples: Vec<FeatureAllocation>) -> Result<Self, Error> {
+        let weights = vec![1.0; samples.len()];
+        Self::with_weights(samples, weights)
+    }
+
+    /// Posterior samples with importance weights, e.g., from sequential Monte Carlo or importance
+    /// sampling.  The weights need not sum to one.  Samples with zero weight are discarded, and
+    /// the weight of a distinct sample is the sum of the weights of its occurrences.
+    pub fn with_weights(samples: Vec<FeatureAllocation>, weights: Vec<f64>) -> Result<Self, Error> {
+        let n_items = match samples.first() {
+            Some(z) => z.n_items(),
+            None => return Err(Error::NoSamples),
+        };
+        let mut x = Self {
+            samples: Vec::new(),
+            counts: Vec::new(),
+            weights: Vec::new(),
+            n_samples: 0,
+            n_items,
+            max_n_features: 0,
+        };
+        x.extend(samples, weights)?;
+        Ok(x)
+    }
+
+    /// Adds posterior samples with importance weights, as in [`Samples::with_weights`].  Distinct
+    /// samples which are new are appended, so the indices of the existing ones do not change.
+    /// Nothing is added if an error is returned.
+    pub fn extend(
+        &mut self,
+        samples: Vec<FeatureAllocation>,
+        weights: Vec<f64>,
+    ) -> Result<(), Error> {
+        if samples.iter().any(|z| z.n_items() != self.n_items) {
+            return Err(Error::InconsistentItems);
+        }
+        if weights.len() != samples.len()
+            || weights.iter().any(|w| !w.is_finite() || *w < 0.0)
+            || (self.n_samples == 0 && weights.iter().all(|&w| w == 0.0))
+        {
+            return Err(Error::InvalidWeights);
+        }
+        let mut index: HashMap<_, _> = self
+            .samples
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(k, z)| (z, k))
+            .collect();
+        for (z, w) in samples.into_iter().zip(weights) {
+            if w == 0.0 {
+                continue;
+            }
+            self.n_samples += 1;
+            let z = z.left_ordered();
+            match index.get(&z) {
+                Some(&k) => {
+                    self.counts[k] += 1;
+                    self.weights[k] += w;
+                }
+                None => {
+                    self.max_n_features = self.max_n_features.max(z.n_features());
+                    index.insert(z.clone(), self.samples.len());
+                    self.samples.push(z);
+                    self.counts.push(1);
+                    self.weights.push(w);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of samples having positive weight, counting repeats.
+    pub fn len(&self) -> usize {
+        self.n_samples
+    }
+
+    /// Always `false`, since [`Samples::new`] rejects an empty collection.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The number of distinct samples.
+    pub fn n_unique(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn n_items(&self) -> usize {
+        self.n_items
+    }
+
+    /// The largest number of features among all of the samples.
+    pub fn max_n_features(&self) -> usize {
+        self.max_n_features
+    }
+
+    /// A hash of the distinct samples and their weights (FNV-1a), which identifies the samples
+    /// of a checkpoint.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = 0xcbf29ce484222325_u64;
+        let mut update = |x: u64| {
+            for byte in x.to_le_bytes() {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        update(self.n_items as u64);
+        for (z, w) in self.samples.iter().zip(&self.weights) {
+            update(z.n_features() as u64);
+            for j in 0..z.n_features() {
+                z.column_bits(j).iter().for_each(|&x| update(x));
+            }
+            update(w.to_bits());
+        }
+        hash
+    }
+
+    /// The distinct sample at `index`, in the order of first occurrence.
+    pub fn get(&self, index: usize) -> Option<&FeatureAllocation> {
+        self.samples.get(index)
+    }
+
+    /// The distinct samples along with the number of times each occurs.
+    pub fn iter(&self) -> impl Iterator<Item = (&FeatureAllocation, usize)> {
+        self.samples.iter().zip(self.counts.iter().copied())
+    }
+
+    /// The distinct samples.
+    pub fn as_slice(&self) -> &[FeatureAllocation] {
+        &self.samples[..]
+    }
+
+    /// The number of times each distinct sample occurs.
+    pub fn counts(&self) -> &[usize] {
+        &self.counts[..]
+    }
+
+    /// The weight of each distinct sample in the expected loss, which for unweighted samples is
+    /// the number of times it occurs.
+    pub fn weights(&self) -> &[f64] {
+        &self.weights[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_samples_are_counted_once() {
+        let z = FeatureAllocation::from_fn(4, 2, |i, j| i <= j + 1);
+        let permuted = FeatureAllocation::from_fn(4, 3, |i, j| j != 1 && i <= 2 - j / 2);
+        let other = FeatureAllocation::from_fn(4, 1, |i, _| i == 3);
+        let samples = Samples::new(vec![z.clone(), other, permuted, z]).unwrap();
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples.n_unique(), 2);
+        assert_eq!(samples.counts(), &[3, 1]);
+        assert_eq!(samples.max_n_features(), 2);
+        assert!(samples.get(0).unwrap().get(0, 0));
+    }
+
+    #[test]
+    fn weights_are_summed_over_repeats() {
+        let z = FeatureAllocation::from_fn(3, 1, |i, _| i == 0);
+        let other = FeatureAllocation::from_fn(3, 1, |i, _| i == 1);
+        let samples = Samples::with_weights(
+            vec![z.clone(), other.clone(), z.clone(), other],
+            vec![0.5, 0.0, 2.0, 1.5],
+        )
+        .unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples.counts(), &[2, 1]);
+        assert_eq!(samples.weights(), &[2.5, 1.5]);
+        for weights in [
+            vec![1.0],
+            vec![0.0, 0.0],
+            vec![1.0, -1.0],
+            vec![1.0, f64::NAN],
+        ] {
+            assert_eq!(
+                Samples::with_weights(vec![z.clone(), z.clone()], weights).unwrap_err(),
+                Error::InvalidWeights
+            );
+        }
+    }
+
+    #[test]
+    fn extending_appends_new_samples() {
+        let z = FeatureAllocation::from_fn(3, 1, |i, _| i == 0);
+        let other = FeatureAllocation::from_fn(3, 2, |i, j| i == j);
+        let mut samples = Samples::new(vec![z.clone()]).unwrap();
+        samples
+            .extend(vec![other.clone(), z.clone()], vec![2.0, 0.5])
+            .unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples.counts(), &[2, 1]);
+        assert_eq!(samples.weights(), &[1.5, 2.0]);
+        assert_eq!(samples.max_n_features(), 2);
+        assert_eq!(
+            samples.extend(vec![FeatureAllocation::zeros(4, 1)], vec![1.0]),
+            Err(Error::InconsistentItems)
+        );
+        assert_eq!(samples.extend(vec![z], vec![]), Err(Error::InvalidWeights));
+        assert_eq!(samples.len(), 3);
+    }
+}
@@ -0,0 +1,2682 @@
+use crate::checkpoint;
+use crate::loss::{
+    aligned_proportions, alignments, expected_loss_from_samples, expected_loss_from_solver_states,
+    expected_loss_from_weight_matrices_if_flip_bit, expected_loss_on_batch, flip_bit, loss,
+    make_solver_states, make_solver_states_on_batch, make_weight_matrices, make_weight_matrix,
+    push_column, refresh_solver_states, swap_remove_column, update_solver_states,
+    update_solver_states_on_batch, Cancellation, SolverState, WeightMatrix,
+};
+use crate::timers::{EchoTimer, PeriodicTimer};
+use crate::{Error, FaroLoss, FeatureAllocation, Samples, Solver};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+/// The fractions of [`Fangs::max_seconds`] after which initial estimates stop being obtained and
+/// ranked.
+const ALIGNMENT_SHARE: f64 = 0.25;
+const RANKING_SHARE: f64 = 0.5;
+
+/// Receives status messages from a search and tells the search whether to stop early.
+///
+/// Methods are only ever called from the thread that started the search.
+pub trait Monitor {
+    /// Display a status message, returning `true` if a user interrupt was caught meanwhile.
+    fn print(&mut self, msg: &str) -> bool;
+
+    /// Returns `true` if the user has asked to interrupt the search.
+    fn interrupted(&mut self) -> bool;
+}
+
+/// A monitor which displays nothing and never interrupts.
+pub struct Silent;
+
+impl Monitor for Silent {
+    fn print(&mut self, _msg: &str) -> bool {
+        false
+    }
+
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Sweeten by proposing random single-element changes.
+    Stochastic,
+    /// Sweeten by proposing random single-element changes, where changes which increase the
+    /// expected loss are sometimes accepted according to a temperature [`Schedule`].
+    Annealing,
+    /// Sweeten by repeatedly making the best single-element change.
+    Deterministic,
+    /// Like [`Algorithm::Stochastic`], except that the initial estimates are all the posterior
+    /// samples, together with a copy of each reduced to a random number of its features, rather
+    /// than estimates obtained from baseline samples.
+    Draws,
+    /// Make the best single-element changes starting from a matrix of zeros.
+    DoubleGreedy,
+    /// Evaluate every feature allocation with up to [`Fangs::max_features`] features, which
+    /// gives the exact minimizer of the expected loss for small problems.
+    Exhaustive,
+}
+
+/// How the temperature of simulated annealing decreases over the sweetening iterations.  A
+/// change which increases the expected loss by `d` is accepted with probability
+/// `exp(-d / temperature)`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Schedule {
+    /// Start at `temperature` and multiply by `rate` after every iteration.
+    Geometric { temperature: f64, rate: f64 },
+    /// Start at `temperature` and decrease linearly to zero at the last iteration.
+    Linear { temperature: f64 },
+    /// Start at `temperature` and, after every 100 iterations, cool by 10% if the proportion of
+    /// accepted changes exceeded `target` and otherwise warm by 10%.
+    Adaptive { temperature: f64, target: f64 },
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::Geometric {
+            temperature: 0.5,
+            rate: 0.995,
+        }
+    }
+}
+
+impl Schedule {
+    fn temperature(&self) -> f64 {
+        match *self {
+            Schedule::Geometric { temperature, .. }
+            | Schedule::Linear { temperature }
+            | Schedule::Adaptive { temperature, .. } => temperature,
+        }
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let ok = self.temperature() >= 0.0
+            && self.temperature().is_finite()
+            && match *self {
+                Schedule::Geometric { rate, .. } => rate > 0.0 && rate <= 1.0,
+                Schedule::Linear { .. } => true,
+                Schedule::Adaptive { target, .. } => target > 0.0 && target < 1.0,
+            };
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::InvalidSchedule)
+        }
+    }
+}
+
+/// The point estimate found by a search, along with details on how it was found.
+#[derive(Debug, Clone)]
+pub struct FangsResult {
+    pub estimate: FeatureAllocation,
+    pub expected_loss: f64,
+    /// The sweetening iteration at which the estimate was found.
+    pub iteration: usize,
+    /// The number of sweetening iterations performed.
+    pub n_iterations: usize,
+    /// The (zero-based) index of the sweetened initial estimate from which the estimate was found.
+    pub which_sweet: usize,
+    /// The number of accepted and rejected changes while sweetening the initial estimate from
+    /// which the estimate was found.
+    pub n_accepts: usize,
+    pub n_rejects: usize,
+    pub seconds_initialization: f64,
+    /// The parts of the initialization phase spent obtaining the initial estimates (e.g., by
+    /// aligning the samples to the baselines) and ranking them by expected loss.
+    pub seconds_alignment: f64,
+    pub seconds_ranking: f64,
+    pub seconds_sweetening: f64,
+    pub seconds_total: f64,
+    /// Which phases were cut short by [`Fangs::max_seconds`].
+    pub truncated: Truncated,
+    /// The seed of the random number generator, which reproduces the search when supplied to
+    /// [`Fangs::seed`].
+    pub seed: u64,
+    /// The outcome of sweetening each initial estimate, in the order of their expected loss before
+    /// sweetening, which is empty unless requested by [`Fangs::trace`].
+    pub sweets: Vec<SweetResult>,
+}
+
+/// Which phases of a search were cut short by [`Fangs::max_seconds`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Truncated {
+    /// Fewer initial estimates were obtained than requested by [`Fangs::n_init`].
+    pub alignment: bool,
+    /// Some initial estimates were dropped without computing their expected loss, or fewer
+    /// were sweetened than requested by [`Fangs::n_sweet`].
+    pub ranking: bool,
+    /// Sweetening stopped before all iterations were performed (or, for
+    /// [`Algorithm::Deterministic`] and [`Algorithm::DoubleGreedy`], before no single-element
+    /// change improved the estimate).
+    pub sweetening: bool,
+}
+
+/// The outcome of sweetening one of the initial estimates.
+#[derive(Debug, Clone)]
+pub struct SweetResult {
+    /// The best estimate found while sweetening.
+    pub estimate: FeatureAllocation,
+    pub expected_loss: f64,
+    /// The sweetening iteration at which the estimate was found.
+    pub iteration: usize,
+    pub n_accepts: usize,
+    pub n_rejects: usize,
+    /// The expected loss when sweetening starts and after each accepted change.  For
+    /// [`Algorithm::Deterministic`], which has no iterations, only the expected losses at the
+    /// start and end of sweetening are recorded.
+    pub trace: Vec<TracePoint>,
+}
+
+/// The expected loss of the current estimate at a point in the sweetening phase.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TracePoint {
+    pub iteration: usize,
+    pub expected_loss: f64,
+    /// The elapsed time since the start of the search.
+    pub seconds: f64,
+}
+
+/// A builder for the feature allocation neighborhood greedy search.
+#[derive(Debug, Clone)]
+pub struct Fangs {
+    loss: FaroLoss,
+    n_init: usize,
+    n_sweet: usize,
+    n_iterations: usize,
+    max_seconds: f64,
+    n_cores: usize,
+    algorithm: Algorithm,
+    quiet: bool,
+    seed: Option<u64>,
+    schedule: Schedule,
+    column_moves: f64,
+    trace: bool,
+    initial_estimates: Vec<FeatureAllocation>,
+    checkpoint: Option<(PathBuf, f64)>,
+    mini_batch: Option<(usize, usize)>,
+    max_features: usize,
+}
+
+impl Default for Fangs {
+    fn default() -> Self {
+        Self {
+            loss: FaroLoss::new(1.0).unwrap(),
+            n_init: 16,
+            n_sweet: 4,
+            n_iterations: 0,
+            max_seconds: 60.0,
+            n_cores: 0,
+            algorithm: Algorithm::Stochastic,
+            quiet: false,
+            seed: None,
+            schedule: Schedule::default(),
+            column_moves: 0.0,
+            trace: false,
+            initial_estimates: Vec::new(),
+            checkpoint: None,
+            mini_batch: None,
+            max_features: 0,
+        }
+    }
+}
+
+impl Fangs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn loss(mut self, loss: FaroLoss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// The number of initial estimates obtained by aligning the samples to randomly selected
+    /// baseline samples, which [`Algorithm::Draws`] ignores.
+    pub fn n_init(mut self, n_init: usize) -> Self {
+        self.n_init = n_init;
+        self
+    }
+
+    /// The number of initial estimates (those with the lowest expected loss) to sweeten.
+    pub fn n_sweet(mut self, n_sweet: usize) -> Self {
+        self.n_sweet = n_sweet;
+        self
+    }
+
+    /// The number of proposed changes in the stochastic sweetening phase, where zero means the
+    /// number of items times the number of features.
+    pub fn n_iterations(mut self, n_iterations: usize) -> Self {
+        self.n_iterations = n_iterations;
+        self
+    }
+
+    /// The time limit of the whole search, measured from its start.  Initial estimates stop being
+    /// obtained after a quarter of the time limit and ranked after half of it (but at least one
+    /// is always obtained, ranked, and sweetened), which leaves the rest for sweetening.  The
+    /// phases which were cut short are reported in [`FangsResult::truncated`].
+    pub fn max_seconds(mut self, max_seconds: f64) -> Self {
+        self.max_seconds = max_seconds;
+        self
+    }
+
+    /// The number of threads, where zero means all cores on the system.
+    pub fn n_cores(mut self, n_cores: usize) -> Self {
+        self.n_cores = n_cores;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// The temperature schedule for [`Algorithm::Annealing`].
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// The probability that a proposed change in the stochastic sweetening phase is a
+    /// column-level move rather than a single-element change.  A column-level move adds a feature
+    /// (having one item, copied from a sample, or duplicating a feature), deletes a feature,
+    /// merges two features, or splits a feature in two.
+    pub fn column_moves(mut self, column_moves: f64) -> Self {
+        self.column_moves = column_moves;
+        self
+    }
+
+    /// Initial estimates (with any number of features) to consider along with those obtained
+    /// from baseline samples, e.g., to polish an estimate from a previous search or another
+    /// method.  All are ranked by expected loss before choosing which to sweeten.  The
+    /// double-greedy algorithm starts from the best of them instead of the empty feature
+    /// allocation.
+    pub fn initial_estimates(mut self, initial_estimates: Vec<FeatureAllocation>) -> Self {
+        self.initial_estimates = initial_estimates;
+        self
+    }
+
+    /// Whether to report the outcome of sweetening every initial estimate, including a trace of
+    /// the expected loss, in [`FangsResult::sweets`].
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Save the state of the sweetening phase of [`Algorithm::Stochastic`],
+    /// [`Algorithm::Annealing`], and [`Algorithm::Draws`] to the file `path`, at most every
+    /// `seconds` seconds and when sweetening stops, so that an interrupted search can be
+    /// continued by [`Fangs::resume`].
+    pub fn checkpoint(mut self, path: impl Into<PathBuf>, seconds: f64) -> Self {
+        self.checkpoint = Some((path.into(), seconds));
+        self
+    }
+
+    /// Score each change proposed in the stochastic sweetening phase, including column-level
+    /// moves, on a random mini-batch of `size` distinct samples rather than on all of them, which
+    /// makes large numbers of samples tractable.  The change is accepted based on the estimated change in
+    /// the expected loss.  Every `period` iterations (and when sweetening stops), the expected
+    /// loss is computed exactly, and only then can the best estimate change, so the reported
+    /// expected loss is always exact.  A `size` of zero (or at least the number of distinct
+    /// samples) uses all samples.  With mini-batches, stopping and continuing a search changes
+    /// its course, since sweetening ends with an exact evaluation.
+    pub fn mini_batch(mut self, size: usize, period: usize) -> Self {
+        self.mini_batch = (size > 0).then_some((size, period.max(1)));
+        self
+    }
+
+    /// The maximum number of features of the feature allocations evaluated by
+    /// [`Algorithm::Exhaustive`], where zero means the maximum number of features of the samples.
+    pub fn max_features(mut self, max_features: usize) -> Self {
+        self.max_features = max_features;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Seed the random number generator, which makes the result depend only on the seed and the
+    /// other arguments (and not on the number of threads) unless the search is cut short by
+    /// [`Fangs::max_seconds`].  Without a seed, one is drawn from the operating system.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn run(&self, samples: &Samples, monitor: &mut impl Monitor) -> Result<FangsResult, Error> {
+        let pool = self.pool();
+        self.check(samples)?;
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let result = match self.algorithm {
+            Algorithm::Stochastic | Algorithm::Draws => {
+                self.fangs(samples, false, false, seed, &pool, monitor)
+            }
+            Algorithm::Annealing => {
+                self.schedule.check()?;
+                self.fangs(samples, false, true, seed, &pool, monitor)
+            }
+            Algorithm::Deterministic => self.fangs(samples, true, false, seed, &pool, monitor),
+            Algorithm::DoubleGreedy => Ok(self.double_greedy(samples, &pool, monitor)),
+            Algorithm::Exhaustive => self.exhaustive(samples, &pool, monitor),
+        };
+        result.map(|result| FangsResult { seed, ..result })
+    }
+
+    /// Continues a search from a checkpoint written as requested by [`Fangs::checkpoint`].  The
+    /// parameters of the original search are used, except for the time limit, the number of
+    /// threads, whether to be quiet, and where to write checkpoints, which are taken from this
+    /// builder.  The `samples` must be the same as those of the original search, and then the
+    /// result is the same as if the original search had not been stopped (provided that neither
+    /// is cut short by [`Fangs::max_seconds`]).
+    pub fn resume(
+        &self,
+        path: impl AsRef<Path>,
+        samples: &Samples,
+        monitor: &mut impl Monitor,
+    ) -> Result<FangsResult, Error> {
+        let pool = self.pool();
+        let mut state: Sweetening = checkpoint::read(path.as_ref())?;
+        if state.fingerprint != samples.fingerprint() {
+            return Err(Error::CheckpointMismatch);
+        }
+        let mut timer = EchoTimer::new();
+        let n_iterations = state.n_iterations;
+        self.sweeten(&mut state, samples, n_iterations, &timer, &pool, monitor)?;
+        Ok(self.finish(&state, &mut timer, monitor))
+    }
+
+    /// Performs the initialization phase of a search whose sweetening phase is then performed in
+    /// steps by [`FangsSearch::run`], e.g., to drive the search interactively without repeating
+    /// the initialization.  Only [`Algorithm::Stochastic`] and [`Algorithm::Annealing`] are
+    /// supported.
+    pub fn start(
+        &self,
+        samples: Samples,
+        monitor: &mut impl Monitor,
+    ) -> Result<FangsSearch, Error> {
+        let annealing = match self.algorithm {
+            Algorithm::Stochastic => false,
+            Algorithm::Annealing => {
+                self.schedule.check()?;
+                true
+            }
+            _ => return Err(Error::InvalidAlgorithm),
+        };
+        let pool = self.pool();
+        self.check(&samples)?;
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let mut timer = EchoTimer::new();
+        let state = self.initialize(&samples, annealing, seed, &pool, &mut timer, monitor)?;
+        Ok(FangsSearch {
+            fangs: self.clone(),
+            samples,
+            pool,
+            state,
+        })
+    }
+
+    fn pool(&self) -> ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.n_cores)
+            .build()
+            .unwrap()
+    }
+
+    /// Checks the parameters which do not depend on the algorithm.
+    fn check(&self, samples: &Samples) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&self.column_moves) {
+            return Err(Error::InvalidProbability);
+        }
+        if self
+            .initial_estimates
+            .iter()
+            .any(|z| z.n_items() != samples.n_items())
+        {
+            return Err(Error::InconsistentItems);
+        }
+        let max_features = self.loss.solver().max_features();
+        if samples.max_n_features() > max_features
+            || self
+                .initial_estimates
+                .iter()
+                .any(|z| z.n_features() > max_features)
+            || (matches!(self.algorithm, Algorithm::Exhaustive) && self.max_features > max_features)
+        {
+            return Err(Error::TooLarge);
+        }
+        Ok(())
+    }
+
+    fn fangs(
+        &self,
+        samples: &Samples,
+        use_neighbors: bool,
+        annealing: bool,
+        seed: u64,
+        pool: &ThreadPool,
+        monitor: &mut impl Monitor,
+    ) -> Result<FangsResult, Error> {
+        let mut timer = EchoTimer::new();
+        let mut state = self.initialize(samples, annealing, seed, pool, &mut timer, monitor)?;
+        if use_neighbors {
+            let views = samples.as_slice();
+            let weights = samples.weights();
+            let n_items = samples.n_items();
+            let a = state.a;
+            let cancellation = Cancellation::default();
+            let sweets = &mut state.sweets;
+            let work = |messages: Sender<String>| {
+                pool.install(|| {
+                    sweets
+                        .par_iter_mut()
+                        .map(|sweet| {
+                            let (loss, truncated) = neighborhood_sweeten(
+                                &mut sweet.z,
+                                &mut sweet.weight_matrices[..],
+                                &mut sweet.solver_states,
+                                views,
+                                weights,
+                                n_items,
+                                a,
+                                &cancellation,
+                                pool,
+                                &timer,
+                                &messages,
+                            );
+                            sweet.loss = loss;
+                            sweet.best_loss = loss;
+                            sweet.record(0, timer.total_as_secs_f64());
+                            truncated
+                        })
+                        .collect::<Vec<_>>()
+                })
+            };
+            let (truncated, caught) = watch(work, &cancellation, self.max_seconds, &timer, monitor);
+            if caught {
+                monitor.print("\nCaught user interrupt, so breaking out early.");
+            }
+            state.truncated.sweetening = !caught && truncated.into_iter().any(|x| x);
+            state.seconds_sweetening = timer.total_as_secs_f64() - state.seconds_initialization;
+        } else {
+            let n_iterations = state.n_iterations;
+            self.sweeten(&mut state, samples, n_iterations, &timer, pool, monitor)?;
+        }
+        Ok(self.finish(&state, &mut timer, monitor))
+    }
+
+    /// The initialization phase, which obtains the initial estimates and chooses those to
+    /// sweeten.
+    fn initialize(
+        &self,
+        samples: &Samples,
+        annealing: bool,
+        seed: u64,
+        pool: &ThreadPool,
+        timer: &mut EchoTimer,
+        monitor: &mut impl Monitor,
+    ) -> Result<Sweetening, Error> {
+        let rng = &mut Pcg64Mcg::seed_from_u64(seed);
+        let views = samples.as_slice();
+        let weights = samples.weights();
+        let n_items = samples.n_items();
+        let max_n_features_observed = samples.max_n_features();
+        let a = self.loss.a();
+        let solver = self.loss.solver();
+        let threshold = a / 2.0;
+        let draws = self.algorithm == Algorithm::Draws;
+        let n_baselines = if draws {
+            samples.n_unique()
+        } else {
+            self.n_init.max(1).min(samples.n_unique())
+        };
+        let n_sweet = (self.n_sweet.max(1)).min(n_baselines + self.initial_estimates.len());
+        let mut interrupted = false;
+        if timer.echo() {
+            interrupted |= monitor.print(
+                timer
+                    .stamp(
+                        format!(
+                            "Parsed parameters.  Using {} threads.\n",
+                            pool.current_num_threads()
+                        )
+                        .as_str(),
+                    )
+                    .unwrap()
+                    .as_str(),
+            );
+        }
+        let baselines_with_rngs: Vec<_> = if draws {
+            (0..views.len()).collect()
+        } else {
+            rand::seq::index::sample_weighted(rng, views.len(), |i| weights[i], n_baselines)
+                .unwrap()
+                .into_vec()
+        }
+        .into_iter()
+        .map(|index| {
+            let mut seed = [0_u8; 16];
+            rng.fill_bytes(&mut seed);
+            let new_rng = Pcg64Mcg::from_seed(seed);
+            (&views[index], new_rng)
+        })
+        .collect();
+        // Initial estimates stop being obtained and ranked after fractions of the time limit, but
+        // the first is always kept.
+        let alignment_deadline = self.max_seconds * ALIGNMENT_SHARE;
+        let ranking_deadline = self.max_seconds * RANKING_SHARE;
+        let cancellation = Cancellation::default();
+        let work = |_| {
+            pool.install(|| {
+                baselines_with_rngs
+                    .into_par_iter()
+                    .flat_map_iter(|(view, mut rng)| {
+                        if cancellation.is_cancelled() {
+                            return vec![(view, None, rng)];
+                        }
+                        if draws {
+                            // Each draw is a candidate as is, and so is a copy keeping a random
+                            // number of its features, which has no features without items since
+                            // the samples are left-ordered.
+                            let n_features = view.n_features();
+                            let reduced = (n_features > 1).then(|| {
+                                let n_selected = rng.gen_range(1..n_features);
+                                let selected_columns =
+                                    rand::seq::index::sample(&mut rng, n_features, n_selected)
+                                        .into_vec();
+                                let z = FeatureAllocation::from_fn(n_items, n_selected, |i, j| {
+                                    view.get(i, selected_columns[j])
+                                });
+                                let mut seed = [0_u8; 16];
+                                rng.fill_bytes(&mut seed);
+                                (view, Some(z), Pcg64Mcg::from_seed(seed))
+                            });
+                            let draw = (view, Some(with_a_feature(view)), rng);
+                            return std::iter::once(draw).chain(reduced).collect();
+                        }
+                        let z = alignments(view, views, a, solver, &cancellation, pool).map(
+                            |permutations| {
+                                let elementwise_means = aligned_proportions(
+                                    views,
+                                    weights,
+                                    &permutations,
+                                    max_n_features_observed,
+                                );
+                                with_a_feature(&FeatureAllocation::from_fn(
+                                    n_items,
+                                    max_n_features_observed,
+                                    |i, j| elementwise_means[[i, j]] >= threshold,
+                                ))
+                            },
+                        );
+                        vec![(view, z, rng)]
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let (initials_with_rngs, caught) =
+            watch(work, &cancellation, alignment_deadline, timer, monitor);
+        if caught {
+            return Err(Error::Interrupted);
+        }
+        let mut truncated = Truncated {
+            alignment: initials_with_rngs.iter().any(|(_, z, _)| z.is_none()),
+            ..Truncated::default()
+        };
+        let initials_with_rngs = initials_with_rngs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(k, (view, z, rng))| {
+                // If the first alignment is cut short, the baseline itself is its initial estimate.
+                let z = z.or_else(|| (k == 0).then(|| with_a_feature(view)))?;
+                Some((z, rng))
+            })
+            .chain(self.initial_estimates.iter().map(|z| {
+                let mut seed = [0_u8; 16];
+                rng.fill_bytes(&mut seed);
+                (with_a_feature(z), Pcg64Mcg::from_seed(seed))
+            }))
+            .collect::<Vec<_>>();
+        if timer.echo() {
+            interrupted |= monitor.print(
+                timer
+                    .stamp(if draws {
+                        "Made reduced copies of all posterior draws.\n"
+                    } else {
+                        "Made initial estimates.\n"
+                    })
+                    .unwrap()
+                    .as_str(),
+            );
+        }
+        let seconds_alignment = timer.total_as_secs_f64();
+        // The weight matrices are only kept for the estimates to sweeten, since there may be as
+        // many estimates as samples.
+        let mut initials = Vec::with_capacity(initials_with_rngs.len());
+        for (z, rng) in initials_with_rngs {
+            if interrupted || monitor.interrupted() {
+                return Err(Error::Interrupted);
+            }
+            // The first initial estimate is ranked regardless of the time limit.
+            let deadline = if initials.is_empty() {
+                f64::INFINITY
+            } else if timer.total_as_secs_f64() < ranking_deadline {
+                ranking_deadline
+            } else {
+                truncated.ranking = true;
+                break;
+            };
+            let cancellation = Cancellation::default();
+            let work =
+                |_| expected_loss_from_samples(&z, views, weights, a, solver, &cancellation, pool);
+            match watch(work, &cancellation, deadline, timer, monitor) {
+                (_, true) => return Err(Error::Interrupted),
+                (Some(loss), false) => initials.push((z, loss, rng)),
+                (None, false) => {
+                    truncated.ranking = true;
+                    break;
+                }
+            }
+        }
+        if timer.echo() {
+            interrupted |= monitor.print(
+                timer
+                    .stamp("Computed expected loss for all initial estimates.\n")
+                    .unwrap()
+                    .as_str(),
+            );
+        }
+        initials.sort_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+        initials.truncate(n_sweet);
+        let temperature = if annealing {
+            self.schedule.temperature()
+        } else {
+            0.0
+        };
+        let mut sweets = Vec::with_capacity(initials.len());
+        for (id, (z, _, rng)) in initials.into_iter().enumerate() {
+            if interrupted || monitor.interrupted() {
+                return Err(Error::Interrupted);
+            }
+            let deadline = if id == 0 {
+                f64::INFINITY
+            } else if timer.total_as_secs_f64() < ranking_deadline {
+                ranking_deadline
+            } else {
+                truncated.ranking = true;
+                break;
+            };
+            let cancellation = Cancellation::default();
+            let work = |_| {
+                let weight_matrices =
+                    make_weight_matrices(&z, views, a, solver, &cancellation, pool)?;
+                let solver_states = make_solver_states(&weight_matrices, &cancellation, pool)?;
+                Some((weight_matrices, solver_states))
+            };
+            let (weight_matrices, solver_states) =
+                match watch(work, &cancellation, deadline, timer, monitor) {
+                    (_, true) => return Err(Error::Interrupted),
+                    (Some(x), false) => x,
+                    (None, false) => {
+                        truncated.ranking = true;
+                        break;
+                    }
+                };
+            let loss = expected_loss_from_solver_states(&weight_matrices, &solver_states, weights);
+            sweets.push(Sweet {
+                z,
+                loss,
+                weight_matrices,
+                solver_states,
+                stale: vec![Vec::new(); views.len()],
+                id,
+                best_z: None,
+                best_loss: loss,
+                when: 1,
+                n_accepts: 0,
+                n_rejects: 0,
+                temperature,
+                n_accepts_before_window: 0,
+                rng,
+                trace: self.trace.then(|| {
+                    vec![TracePoint {
+                        iteration: 0,
+                        expected_loss: loss,
+                        seconds: timer.total_as_secs_f64(),
+                    }]
+                }),
+                behind: false,
+            });
+        }
+        if timer.echo() {
+            interrupted |= monitor.print(
+                timer
+                    .stamp("Computed weight matrices for sweetenings.\n")
+                    .unwrap()
+                    .as_str(),
+            );
+        }
+        if interrupted {
+            return Err(Error::Interrupted);
+        }
+        let n_iterations = if self.n_iterations == 0 {
+            sweets
+                .iter()
+                .map(|x| x.z.n_items() * x.z.n_features())
+                .max()
+                .unwrap_or(0)
+        } else {
+            self.n_iterations
+        };
+        let seconds_initialization = timer.total_as_secs_f64();
+        Ok(Sweetening {
+            fingerprint: samples.fingerprint(),
+            seed,
+            a,
+            solver,
+            annealing,
+            schedule: self.schedule,
+            column_moves: self.column_moves,
+            mini_batch: self
+                .mini_batch
+                .filter(|&(size, _)| size < samples.n_unique()),
+            n_iterations,
+            trace: self.trace,
+            iteration: 0,
+            seconds_initialization,
+            seconds_alignment,
+            seconds_ranking: seconds_initialization - seconds_alignment,
+            seconds_sweetening: 0.0,
+            truncated,
+            sweets,
+        })
+    }
+
+    /// The stochastic sweetening phase, continuing from `state` until iteration `until` and saving
+    /// the state in checkpoints.
+    fn sweeten(
+        &self,
+        state: &mut Sweetening,
+        samples: &Samples,
+        until: usize,
+        timer: &EchoTimer,
+        pool: &ThreadPool,
+        monitor: &mut impl Monitor,
+    ) -> Result<(), Error> {
+        let views = samples.as_slice();
+        let weights = samples.weights();
+        let max_seconds = self.max_seconds;
+        let quiet = self.quiet;
+        let status_file = match std::env::var("FANGS_STATUS") {
+            Ok(x) => Path::new(x.as_str()).to_owned(),
+            _ => std::env::current_dir()
+                .unwrap_or_default()
+                .join("FANGS_STATUS"),
+        };
+        let start = timer.total_as_secs_f64();
+        let seconds_before = state.seconds_sweetening;
+        // The elapsed time since the start of the original search is `offset` plus the total time
+        // of the timer.
+        let offset = state.seconds_initialization + seconds_before - start;
+        let mut period_timer = PeriodicTimer::new(1.0);
+        let mut latest_checkpoint = Instant::now();
+        let n_iterations = state.n_iterations;
+        let mini_batch = state.mini_batch;
+        // Proposals in flight are abandoned once the user interrupts or time runs out, leaving
+        // their sweets behind so that they are made first when sweetening continues.
+        let cancellation = Cancellation::default();
+        let work = |messages: Sender<String>| -> Result<(), Error> {
+            while !cancellation.is_cancelled() {
+                if state.sweets.iter().all(|sweet| !sweet.behind) {
+                    if state.iteration >= until || timer.total_as_secs_f64() >= max_seconds {
+                        break;
+                    }
+                    state.iteration += 1;
+                    for sweet in &mut state.sweets {
+                        sweet.behind = true;
+                    }
+                }
+                let iteration = state.iteration;
+                pool.install(|| {
+                    state.sweets.par_iter_mut().for_each(|sweet| {
+                        if !sweet.behind {
+                            return;
+                        }
+                        let n_accepts = sweet.n_accepts;
+                        if !sweet.propose(
+                            views,
+                            weights,
+                            state.a,
+                            &cancellation,
+                            pool,
+                            iteration,
+                            state.column_moves,
+                            mini_batch,
+                        ) {
+                            return;
+                        }
+                        sweet.behind = false;
+                        match mini_batch {
+                            // The trace only has exact expected losses.  An evaluation cut short
+                            // is made up for by the one after sweetening.
+                            Some((_, period)) => {
+                                if iteration % period == 0
+                                    && sweet
+                                        .evaluate(weights, iteration, &cancellation, pool)
+                                        .is_some()
+                                {
+                                    sweet.record(iteration, offset + timer.total_as_secs_f64());
+                                }
+                            }
+                            None => {
+                                if sweet.n_accepts > n_accepts {
+                                    sweet.record(iteration, offset + timer.total_as_secs_f64());
+                                }
+                            }
+                        }
+                        if state.annealing {
+                            sweet.cool(&state.schedule, iteration, n_iterations);
+                        }
+                    });
+                });
+                if !quiet || status_file.exists() {
+                    period_timer.maybe(iteration == until, || {
+                        if quiet && status_file.exists() {
+                            let _ = messages.send(format!(
+                                "*** {} exists, so forcing status display.\n",
+                                status_file.display()
+                            ));
+                        }
+                        let best = state.sweets.iter().min_by(|x, y| x.cmp_best(y)).unwrap();
+                        let _ = messages.send(format!(
+                            "\rIter. {}: Since iter. {}, E(loss) is {:.4} from #{} with {} accept{}.",
+                            iteration,
+                            best.when,
+                            best.best_loss,
+                            best.id + 1,
+                            best.n_accepts,
+                            if best.n_accepts == 1 { "" } else { "s" }
+                        ));
+                    });
+                }
+                if let Some((path, seconds)) = &self.checkpoint {
+                    if latest_checkpoint.elapsed().as_secs_f64() >= *seconds {
+                        state.seconds_sweetening =
+                            seconds_before + timer.total_as_secs_f64() - start;
+                        checkpoint::write(path, state)?;
+                        latest_checkpoint = Instant::now();
+                    }
+                }
+            }
+            Ok(())
+        };
+        let (result, interrupted) = watch(work, &cancellation, max_seconds, timer, monitor);
+        result?;
+        if interrupted {
+            monitor.print("\nCaught user interrupt, so breaking out early.");
+        }
+        if let Some((_, period)) = mini_batch {
+            let iteration = state.iteration;
+            pool.install(|| {
+                state.sweets.par_iter_mut().for_each(|sweet| {
+                    if iteration % period != 0 || cancellation.is_cancelled() {
+                        sweet
+                            .evaluate(weights, iteration, &Cancellation::default(), pool)
+                            .unwrap();
+                        sweet.record(iteration, offset + timer.total_as_secs_f64());
+                    }
+                })
+            });
+        }
+        state.seconds_sweetening = seconds_before + timer.total_as_secs_f64() - start;
+        state.truncated.sweetening = (state.iteration < until
+            || state.sweets.iter().any(|sweet| sweet.behind))
+            && !interrupted;
+        if let Some((path, _)) = &self.checkpoint {
+            checkpoint::write(path, state)?;
+        }
+        Ok(())
+    }
+
+    fn finish(
+        &self,
+        state: &Sweetening,
+        timer: &mut EchoTimer,
+        monitor: &mut impl Monitor,
+    ) -> FangsResult {
+        if !self.quiet {
+            monitor.print("\n");
+        }
+        if timer.echo() {
+            monitor.print(
+                timer
+                    .stamp("Sweetened best initial estimates.\n")
+                    .unwrap()
+                    .as_str(),
+            );
+        }
+        let result = state.result();
+        if timer.echo() {
+            let sweet = state.best();
+            monitor.print(
+                format!(
+                    "Best result is {} from sweetening estimate {} at iteration {} after {} accept{}.\n",
+                    sweet.best_loss,
+                    sweet.id + 1,
+                    sweet.when + 1,
+                    sweet.n_accepts,
+                    if sweet.n_accepts == 1 { "" } else { "s" }
+                )
+                .as_str(),
+            );
+            monitor.print(timer.stamp("Finalized results.\n").unwrap().as_str());
+        }
+        result
+    }
+
+    fn double_greedy(
+        &self,
+        samples: &Samples,
+        pool: &ThreadPool,
+        monitor: &mut impl Monitor,
+    ) -> FangsResult {
+        let timer = EchoTimer::new();
+        let views = samples.as_slice();
+        let weights = samples.weights();
+        let n_items = samples.n_items();
+        let a = self.loss.a();
+        let solver = self.loss.solver();
+        let mut z = self
+            .initial_estimates
+            .iter()
+            .map(|z| {
+                let z = with_a_feature(z);
+                let loss = expected_loss_from_samples(
+                    &z,
+                    views,
+                    weights,
+                    a,
+                    solver,
+                    &Cancellation::default(),
+                    pool,
+                )
+                .unwrap();
+                (z, loss)
+            })
+            .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap())
+            .map(|(z, _)| z)
+            .unwrap_or_else(|| FeatureAllocation::zeros(n_items, samples.max_n_features()));
+        let cancellation = Cancellation::default();
+        let mut weight_matrices =
+            make_weight_matrices(&z, views, a, solver, &cancellation, pool).unwrap();
+        let mut solver_states = make_solver_states(&weight_matrices, &cancellation, pool).unwrap();
+        let work = |messages: Sender<String>| {
+            neighborhood_sweeten(
+                &mut z,
+                &mut weight_matrices[..],
+                &mut solver_states,
+                views,
+                weights,
+                n_items,
+                a,
+                &cancellation,
+                pool,
+                &timer,
+                &messages,
+            )
+        };
+        let ((loss, truncated), caught) =
+            watch(work, &cancellation, self.max_seconds, &timer, monitor);
+        if caught {
+            monitor.print("\nCaught user interrupt, so breaking out early.");
+        }
+        let seconds_total = timer.total_as_secs_f64();
+        FangsResult {
+            estimate: z,
+            expected_loss: loss,
+            iteration: 0,
+            n_iterations: 0,
+            which_sweet: 0,
+            n_accepts: 0,
+            n_rejects: 0,
+            seconds_initialization: 0.0,
+            seconds_alignment: 0.0,
+            seconds_ranking: 0.0,
+            seconds_sweetening: seconds_total,
+            seconds_total,
+            truncated: Truncated {
+                sweetening: !caught && truncated,
+                ..Truncated::default()
+            },
+            seed: 0,
+            sweets: Vec::new(),
+        }
+    }
+
+    /// Evaluates every feature allocation with up to `max_features` nonempty features, where
+    /// feature allocations differing only in the order of their features are evaluated once by
+    /// taking the features (as bitmasks of their items) in nondecreasing order.  The search
+    /// ignores the time limit, but stops early if interrupted, in which case `n_iterations` is
+    /// less than the number of feature allocations.  Ties are broken by the order of evaluation.
+    fn exhaustive(
+        &self,
+        samples: &Samples,
+        pool: &ThreadPool,
+        monitor: &mut impl Monitor,
+    ) -> Result<FangsResult, Error> {
+        let timer = EchoTimer::new();
+        let views = samples.as_slice();
+        let weights = samples.weights();
+        let n_items = samples.n_items();
+        let a = self.loss.a();
+        let solver = self.loss.solver();
+        let n_features = if self.max_features == 0 {
+            samples.max_n_features()
+        } else {
+            self.max_features
+        };
+        // A feature is a bitmask of its items, where the empty feature stands for no feature.
+        if n_items >= 32 || n_combinations(1 << n_items, n_features) > u32::MAX as u128 {
+            return Err(Error::TooLarge);
+        }
+        let n_columns = 1_u64 << n_items;
+        let evaluate = |columns: &[u64]| {
+            let columns: Vec<u64> = columns.iter().copied().filter(|&c| c != 0).collect();
+            let z =
+                FeatureAllocation::from_fn(n_items, columns.len(), |i, j| columns[j] >> i & 1 == 1);
+            let (sum, total) =
+                views
+                    .iter()
+                    .zip(weights)
+                    .fold((0.0, 0.0), |(sum, total), (zz, w)| {
+                        let loss = match make_weight_matrix(&z, zz, a, solver) {
+                            Some(weight_matrix) => loss(&weight_matrix),
+                            None => 0.0,
+                        };
+                        (sum + w * loss, total + w)
+                    });
+            (z, sum / total)
+        };
+        // Each task is the feature allocations whose first feature is given.
+        let task = |first: u64| {
+            let mut columns = vec![first; n_features.max(1)];
+            let mut best = None;
+            let mut count = 0;
+            loop {
+                let (z, loss) = evaluate(&columns[..n_features]);
+                if best
+                    .as_ref()
+                    .map_or(true, |(_, _, best_loss)| loss < *best_loss)
+                {
+                    best = Some((count, z, loss));
+                }
+                count += 1;
+                // The next nondecreasing sequence with the same first feature.
+                match (1..n_features).rev().find(|&j| columns[j] + 1 < n_columns) {
+                    Some(j) => {
+                        let c = columns[j] + 1;
+                        columns[j..].iter_mut().for_each(|x| *x = c);
+                    }
+                    None => break,
+                }
+            }
+            (count, best.unwrap())
+        };
+        let firsts: Vec<u64> = if n_features == 0 {
+            vec![0]
+        } else {
+            (0..n_columns).collect()
+        };
+        let mut best: Option<(usize, FeatureAllocation, f64)> = None;
+        let mut n_iterations = 0;
+        let chunk_size = 4 * pool.current_num_threads();
+        for chunk in firsts.chunks(chunk_size) {
+            let results: Vec<_> = pool.install(|| chunk.par_iter().map(|&c| task(c)).collect());
+            for (count, (index, z, loss)) in results {
+                if best
+                    .as_ref()
+                    .map_or(true, |(_, _, best_loss)| loss < *best_loss)
+                {
+                    best = Some((n_iterations + index, z, loss));
+                }
+                n_iterations += count;
+            }
+            if monitor.interrupted() {
+                monitor.print("\nCaught user interrupt, so breaking out early.");
+                break;
+            }
+        }
+        let (iteration, estimate, expected_loss) = best.unwrap();
+        let seconds_total = timer.total_as_secs_f64();
+        Ok(FangsResult {
+            estimate,
+            expected_loss,
+            iteration,
+            n_iterations,
+            which_sweet: 0,
+            n_accepts: 0,
+            n_rejects: 0,
+            seconds_initialization: 0.0,
+            seconds_alignment: 0.0,
+            seconds_ranking: 0.0,
+            seconds_sweetening: seconds_total,
+            seconds_total,
+            truncated: Truncated::default(),
+            seed: 0,
+            sweets: Vec::new(),
+        })
+    }
+}
+
+/// A change to the features of an estimate.
+enum ColumnMove {
+    /// Add a feature having the given items.
+    Add(Vec<bool>),
+    /// Delete a feature.
+    Delete(usize),
+    /// Add the items of the second feature to the first and delete the second.
+    Merge(usize, usize),
+    /// Move the given items of a feature to a new feature.
+    Split(usize, Vec<usize>),
+}
+
+/// A search which keeps its state between calls, so that the sweetening phase can be continued
+/// without repeating the initialization.  It is created by [`Fangs::start`].
+pub struct FangsSearch {
+    fangs: Fangs,
+    samples: Samples,
+    pool: ThreadPool,
+    state: Sweetening,
+}
+
+impl FangsSearch {
+    /// Performs up to `n_iterations` more iterations of the sweetening phase, stopping early at
+    /// the time limit set by [`Fangs::max_seconds`] (which applies to each call), and returns
+    /// the best estimate so far.
+    pub fn run(
+        &mut self,
+        n_iterations: usize,
+        monitor: &mut impl Monitor,
+    ) -> Result<FangsResult, Error> {
+        let mut timer = EchoTimer::new();
+        let until = self.state.iteration + n_iterations;
+        self.fangs.sweeten(
+            &mut self.state,
+            &self.samples,
+            until,
+            &timer,
+            &self.pool,
+            monitor,
+        )?;
+        Ok(self.fangs.finish(&self.state, &mut timer, monitor))
+    }
+
+    /// The best estimate so far.
+    pub fn result(&self) -> FangsResult {
+        self.state.result()
+    }
+
+    /// The expected loss of the best estimate so far.
+    pub fn expected_loss(&self) -> f64 {
+        self.state.best().best_loss
+    }
+
+    /// The number of iterations of the sweetening phase performed so far.
+    pub fn iteration(&self) -> usize {
+        self.state.iteration
+    }
+
+    pub fn samples(&self) -> &Samples {
+        &self.samples
+    }
+
+    pub fn set_max_seconds(&mut self, max_seconds: f64) {
+        self.fangs.max_seconds = max_seconds;
+    }
+
+    /// Adds posterior samples with importance weights, as in [`Samples::extend`].  The expected
+    /// losses of the current and best estimates of every sweetening are recomputed, so the best
+    /// estimate may change.
+    pub fn add_samples(
+        &mut self,
+        samples: Vec<FeatureAllocation>,
+        weights: Vec<f64>,
+    ) -> Result<(), Error> {
+        let max_features = self.state.solver.max_features();
+        if samples.iter().any(|z| z.n_features() > max_features) {
+            return Err(Error::TooLarge);
+        }
+        let n_unique = self.samples.n_unique();
+        self.samples.extend(samples, weights)?;
+        let views = self.samples.as_slice();
+        let weights = self.samples.weights();
+        let a = self.state.a;
+        let solver = self.state.solver;
+        let pool = &self.pool;
+        let seconds = self.state.seconds_initialization + self.state.seconds_sweetening;
+        for sweet in &mut self.state.sweets {
+            let all: Vec<usize> = (0..n_unique).collect();
+            refresh_solver_states(
+                &sweet.weight_matrices,
+                &mut sweet.solver_states,
+                &mut sweet.stale,
+                &all,
+                &Cancellation::default(),
+                pool,
+            )
+            .unwrap();
+            sweet.stale.resize(views.len(), Vec::new());
+            let cancellation = Cancellation::default();
+            let weight_matrices =
+                make_weight_matrices(&sweet.z, &views[n_unique..], a, solver, &cancellation, pool)
+                    .unwrap();
+            sweet
+                .solver_states
+                .extend(make_solver_states(&weight_matrices, &cancellation, pool).unwrap());
+            sweet.weight_matrices.extend(weight_matrices);
+            sweet.loss = expected_loss_from_solver_states(
+                &sweet.weight_matrices,
+                &sweet.solver_states,
+                weights,
+            );
+            let best_loss = match &sweet.best_z {
+                Some(z) => {
+                    expected_loss_from_samples(z, views, weights, a, solver, &cancellation, pool)
+                        .unwrap()
+                }
+                None => sweet.loss,
+            };
+            if sweet.loss <= best_loss {
+                if sweet.best_z.take().is_some() {
+                    sweet.when = self.state.iteration;
+                }
+                sweet.best_loss = sweet.loss;
+            } else {
+                sweet.best_loss = best_loss;
+            }
+            sweet.record(self.state.iteration, seconds);
+        }
+        self.state.fingerprint = self.samples.fingerprint();
+        Ok(())
+    }
+}
+
+/// The state of the sweetening phase, which is what a checkpoint saves.
+#[derive(Serialize, Deserialize)]
+struct Sweetening {
+    /// Identifies the samples, so that a search is only resumed with the same samples.
+    fingerprint: u64,
+    seed: u64,
+    a: f64,
+    solver: Solver,
+    annealing: bool,
+    schedule: Schedule,
+    column_moves: f64,
+    /// The size of the mini-batches and the number of iterations between exact evaluations.
+    mini_batch: Option<(usize, usize)>,
+    n_iterations: usize,
+    trace: bool,
+    /// The number of iterations performed so far.
+    iteration: usize,
+    seconds_initialization: f64,
+    seconds_alignment: f64,
+    seconds_ranking: f64,
+    seconds_sweetening: f64,
+    truncated: Truncated,
+    sweets: Vec<Sweet>,
+}
+
+impl Sweetening {
+    /// The sweetening with the lowest expected loss.
+    fn best(&self) -> &Sweet {
+        self.sweets.iter().min_by(|x, y| x.cmp_best(y)).unwrap()
+    }
+
+    fn result(&self) -> FangsResult {
+        let sweet = self.best();
+        FangsResult {
+            expected_loss: sweet.best_loss,
+            iteration: sweet.when,
+            n_iterations: self.iteration,
+            which_sweet: sweet.id,
+            n_accepts: sweet.n_accepts,
+            n_rejects: sweet.n_rejects,
+            seconds_initialization: self.seconds_initialization,
+            seconds_alignment: self.seconds_alignment,
+            seconds_ranking: self.seconds_ranking,
+            seconds_sweetening: self.seconds_sweetening,
+            seconds_total: self.seconds_initialization + self.seconds_sweetening,
+            truncated: self.truncated,
+            seed: self.seed,
+            estimate: sweet
+                .best_z
+                .as_ref()
+                .unwrap_or(&sweet.z)
+                .without_empty_columns(),
+            sweets: if self.trace {
+                self.sweets.iter().map(|sweet| sweet.result()).collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+/// An initial estimate being sweetened by the stochastic search.
+#[derive(Serialize, Deserialize)]
+struct Sweet {
+    z: FeatureAllocation,
+    loss: f64,
+    weight_matrices: Vec<WeightMatrix>,
+    #[serde(with = "checkpoint::solver_states")]
+    solver_states: Vec<SolverState>,
+    /// For each sample, the rows of its weight matrix which changed since its solver state was
+    /// last updated, which are only nonempty with mini-batches.
+    stale: Vec<Vec<usize>>,
+    id: usize,
+    /// The best estimate so far if it is not `z`, which only happens when annealing.
+    best_z: Option<FeatureAllocation>,
+    best_loss: f64,
+    /// The iteration at which the best estimate was found.
+    when: usize,
+    n_accepts: usize,
+    n_rejects: usize,
+    temperature: f64,
+    n_accepts_before_window: usize,
+    rng: Pcg64Mcg,
+    trace: Option<Vec<TracePoint>>,
+    /// Whether the proposal of the current iteration is yet to be made, which is only true
+    /// between iterations when sweetening was cut short in the middle of an iteration.
+    behind: bool,
+}
+
+impl Sweet {
+    /// Orders by the best expected loss, breaking ties by the id so that the order is
+    /// reproducible.
+    fn cmp_best(&self, other: &Self) -> std::cmp::Ordering {
+        self.best_loss
+            .partial_cmp(&other.best_loss)
+            .unwrap()
+            .then(self.id.cmp(&other.id))
+    }
+
+    /// Proposes a change, which is a column-level move with probability `column_moves` and is
+    /// otherwise a flip of a random element.  Either is scored on a random mini-batch of samples
+    /// if `mini_batch` gives its size.  If `cancellation` is cancelled before the change is
+    /// scored, the change is abandoned, the random number generator is rewound, and `false` is
+    /// returned, so that proposing again gives the same change.
+    #[allow(clippy::too_many_arguments)]
+    fn propose(
+        &mut self,
+        views: &[FeatureAllocation],
+        weights: &[f64],
+        a: f64,
+        cancellation: &Cancellation,
+        pool: &ThreadPool,
+        iteration: usize,
+        column_moves: f64,
+        mini_batch: Option<(usize, usize)>,
+    ) -> bool {
+        let rng = self.rng.clone();
+        let proposal = self.try_propose(
+            views,
+            weights,
+            a,
+            cancellation,
+            pool,
+            iteration,
+            column_moves,
+            mini_batch,
+        );
+        if proposal.is_none() {
+            self.rng = rng;
+        }
+        proposal.is_some()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn try_propose(
+        &mut self,
+        views: &[FeatureAllocation],
+        weights: &[f64],
+        a: f64,
+        cancellation: &Cancellation,
+        pool: &ThreadPool,
+        iteration: usize,
+        column_moves: f64,
+        mini_batch: Option<(usize, usize)>,
+    ) -> Option<()> {
+        if column_moves > 0.0 && self.rng.gen::<f64>() < column_moves {
+            if let Some(column_move) = self.choose_column_move(views, weights) {
+                if let Some((size, _)) = mini_batch {
+                    return self.apply_on_batch(
+                        column_move,
+                        views,
+                        weights,
+                        a,
+                        cancellation,
+                        pool,
+                        iteration,
+                        size,
+                    );
+                }
+                let snapshot = (self.z.clone(), self.weight_matrices.clone());
+                self.apply(column_move, views, a);
+                let Some(solver_states) =
+                    make_solver_states(&self.weight_matrices, cancellation, pool)
+                else {
+                    (self.z, self.weight_matrices) = snapshot;
+                    return None;
+                };
+                let new_loss = expected_loss_from_solver_states(
+                    &self.weight_matrices,
+                    &solver_states,
+                    weights,
+                );
+                if self.accept(new_loss, true, iteration, |_| snapshot.0.clone()) {
+                    self.solver_states = solver_states;
+                } else {
+                    (self.z, self.weight_matrices) = snapshot;
+                }
+                return Some(());
+            }
+        }
+        if let Some((size, _)) = mini_batch {
+            return self.propose_on_batch(views, weights, a, cancellation, pool, iteration, size);
+        }
+        let n_features = self.z.n_features();
+        let total_length = self.z.n_items() * n_features;
+        let index = index_1d_to_2d(self.rng.gen_range(0..total_length), n_features);
+        flip_bit(&mut self.z, &mut self.weight_matrices, a, index, views);
+        let Some(solver_states) = update_solver_states(
+            &self.weight_matrices,
+            &self.solver_states,
+            index[1],
+            cancellation,
+            pool,
+        ) else {
+            flip_bit(&mut self.z, &mut self.weight_matrices, a, index, views);
+            return None;
+        };
+        let new_loss =
+            expected_loss_from_solver_states(&self.weight_matrices, &solver_states, weights);
+        let previous = |z: &FeatureAllocation| {
+            let mut z = z.clone();
+            z.flip(index[0], index[1]);
+            z
+        };
+        if self.accept(new_loss, true, iteration, previous) {
+            self.solver_states = solver_states;
+        } else {
+            flip_bit(&mut self.z, &mut self.weight_matrices, a, index, views);
+        }
+        Some(())
+    }
+
+    /// Proposes a flip of a random element, which is accepted based on the change in the
+    /// expected loss estimated from a random mini-batch of `size` distinct samples.  The solver
+    /// states of the other samples are marked as stale rather than updated.
+    #[allow(clippy::too_many_arguments)]
+    fn propose_on_batch(
+        &mut self,
+        views: &[FeatureAllocation],
+        weights: &[f64],
+        a: f64,
+        cancellation: &Cancellation,
+        pool: &ThreadPool,
+        iteration: usize,
+        size: usize,
+    ) -> Option<()> {
+        let n_features = self.z.n_features();
+        let total_length = self.z.n_items() * n_features;
+        let index = index_1d_to_2d(self.rng.gen_range(0..total_length), n_features);
+        let (batch, before) = self.choose_batch(weights, cancellation, pool, size)?;
+        flip_bit(&mut self.z, &mut self.weight_matrices, a, index, views);
+        let Some(solver_states) = update_solver_states_on_batch(
+            &self.weight_matrices,
+            &self.solver_states,
+            index[1],
+            &batch,
+            cancellation,
+            pool,
+        ) else {
+            flip_bit(&mut self.z, &mut self.weight_matrices, a, index, views);
+            return None;
+        };
+        let after = expected_loss_on_batch(
+            &self.weight_matrices,
+            &solver_states.iter().collect::<Vec<_>>(),
+            weights,
+            &batch,
+        );
+        let previous = |z: &FeatureAllocation| {
+            let mut z = z.clone();
+            z.flip(index[0], index[1]);
+            z
+        };
+        if self.accept(self.loss + after - before, false, iteration, previous) {
+            let mut batch = batch.into_iter().zip(solver_states).peekable();
+            for (k, stale) in self.stale.iter_mut().enumerate() {
+                match batch.next_if(|(kk, _)| *kk == k) {
+                    Some((_, state)) => self.solver_states[k] = state,
+                    None => {
+                        if !stale.contains(&index[1]) {
+                            stale.push(index[1]);
+                        }
+                    }
+                }
+            }
+        } else {
+            flip_bit(&mut self.z, &mut self.weight_matrices, a, index, views);
+        }
+        Some(())
+    }
+
+    /// Proposes a column-level move, which is accepted based on the change in the expected loss
+    /// estimated from a random mini-batch of `size` distinct samples.  The move may change the
+    /// dimensions of every weight matrix, so the solver states of the other samples are reset
+    /// and marked as stale in all rows.
+    #[allow(clippy::too_many_arguments)]
+    fn apply_on_batch(
+        &mut self,
+        column_move: ColumnMove,
+        views: &[FeatureAllocation],
+        weights: &[f64],
+        a: f64,
+        cancellation: &Cancellation,
+        pool: &ThreadPool,
+        iteration: usize,
+        size: usize,
+    ) -> Option<()> {
+        let (batch, before) = self.choose_batch(weights, cancellation, pool, size)?;
+        let snapshot = (self.z.clone(), self.weight_matrices.clone());
+        self.apply(column_move, views, a);
+        let Some(solver_states) =
+            make_solver_states_on_batch(&self.weight_matrices, &batch, cancellation, pool)
+        else {
+            (self.z, self.weight_matrices) = snapshot;
+            return None;
+        };
+        let after = expected_loss_on_batch(
+            &self.weight_matrices,
+            &solver_states.iter().collect::<Vec<_>>(),
+            weights,
+            &batch,
+        );
+        if self.accept(self.loss + after - before, false, iteration, |_| {
+            snapshot.0.clone()
+        }) {
+            let n_features = self.z.n_features();
+            let mut batch = batch.into_iter().zip(solver_states).peekable();
+            for (k, (state, stale)) in self
+                .solver_states
+                .iter_mut()
+                .zip(&mut self.stale)
+                .enumerate()
+            {
+                match batch.next_if(|(kk, _)| *kk == k) {
+                    Some((_, new_state)) => *state = new_state,
+                    None => {
+                        *state = SolverState::default();
+                        *stale = (0..n_features).collect();
+                    }
+                }
+            }
+        } else {
+            (self.z, self.weight_matrices) = snapshot;
+        }
+        Some(())
+    }
+
+    /// A random mini-batch of `size` distinct samples, in order, whose solver states are brought
+    /// up to date, along with the weighted mean of their losses, or `None` if `cancellation` is
+    /// cancelled first.
+    fn choose_batch(
+        &mut self,
+        weights: &[f64],
+        cancellation: &Cancellation,
+        pool: &ThreadPool,
+        size: usize,
+    ) -> Option<(Vec<usize>, f64)> {
+        let mut batch = rand::seq::index::sample(&mut self.rng, self.stale.len(), size).into_vec();
+        batch.sort_unstable();
+        refresh_solver_states(
+            &self.weight_matrices,
+            &mut self.solver_states,
+            &mut self.stale,
+            &batch,
+            cancellation,
+            pool,
+        )?;
+        let loss = expected_loss_on_batch(
+            &self.weight_matrices,
+            &batch
+                .iter()
+                .map(|&k| &self.solver_states[k])
+                .collect::<Vec<_>>(),
+            weights,
+            &batch,
+        );
+        Some((batch, loss))
+    }
+
+    /// Replaces the expected loss estimated from mini-batches by the exact expected loss, which
+    /// is the only way the best estimate changes when using mini-batches.  Nothing changes if
+    /// `cancellation` is cancelled first.
+    fn evaluate(
+        &mut self,
+        weights: &[f64],
+        iteration: usize,
+        cancellation: &Cancellation,
+        pool: &ThreadPool,
+    ) -> Option<()> {
+        let all: Vec<usize> = (0..self.stale.len()).collect();
+        refresh_solver_states(
+            &self.weight_matrices,
+            &mut self.solver_states,
+            &mut self.stale,
+            &all,
+            cancellation,
+            pool,
+        )?;
+        self.loss =
+            expected_loss_from_solver_states(&self.weight_matrices, &self.solver_states, weights);
+        if self.loss < self.best_loss {
+            self.best_z = None;
+            self.best_loss = self.loss;
+            self.when = iteration;
+        }
+        Some(())
+    }
+
+    /// Decides whether to keep a change to the estimate, which is kept if the expected loss
+    /// decreases or, when the temperature is positive, with the probability given by the
+    /// annealing criterion.  `previous` gives the estimate before the change from the current one.
+    /// The best estimate only changes if `new_loss` is `exact` rather than estimated.
+    fn accept(
+        &mut self,
+        new_loss: f64,
+        exact: bool,
+        iteration: usize,
+        previous: impl FnOnce(&FeatureAllocation) -> FeatureAllocation,
+    ) -> bool {
+        let increase = new_loss - self.loss;
+        let accept = increase < 0.0
+            || (self.temperature > 0.0
+                && self.rng.gen::<f64>() < (-increase / self.temperature).exp());
+        if accept {
+            self.n_accepts += 1;
+            if exact && new_loss < self.best_loss {
+                self.best_z = None;
+                self.best_loss = new_loss;
+                self.when = iteration;
+            } else if self.best_z.is_none() {
+                self.best_z = Some(previous(&self.z));
+            }
+            self.loss = new_loss;
+        } else {
+            self.n_rejects += 1;
+        }
+        accept
+    }
+
+    /// A random column-level move, or `None` if the chosen kind of move is not possible.  At
+    /// least one feature is always kept.  Features copied from samples come from a sample chosen
+    /// in proportion to its weight.
+    fn choose_column_move(
+        &mut self,
+        views: &[FeatureAllocation],
+        weights: &[f64],
+    ) -> Option<ColumnMove> {
+        let rng = &mut self.rng;
+        let z = &self.z;
+        let n_items = z.n_items();
+        let n_features = z.n_features();
+        // No feature is added beyond the number which the solver handles.
+        let full = self
+            .weight_matrices
+            .first()
+            .map_or(false, |w| n_features >= w.solver().max_features());
+        match rng.gen_range(0..6) {
+            0 | 1 | 2 | 5 if full => None,
+            0 => {
+                let i = rng.gen_range(0..n_items);
+                Some(ColumnMove::Add((0..n_items).map(|ii| ii == i).collect()))
+            }
+            1 => {
+                let zz = &views[WeightedIndex::new(weights).unwrap().sample(rng)];
+                if zz.n_features() == 0 {
+                    None
+                } else {
+                    Some(ColumnMove::Add(
+                        zz.column(rng.gen_range(0..zz.n_features())),
+                    ))
+                }
+            }
+            2 => Some(ColumnMove::Add(z.column(rng.gen_range(0..n_features)))),
+            3 if n_features > 1 => Some(ColumnMove::Delete(rng.gen_range(0..n_features))),
+            4 if n_features > 1 => {
+                let j1 = rng.gen_range(0..n_features);
+                let j2 = rng.gen_range(0..n_features - 1);
+                Some(ColumnMove::Merge(j1, if j2 >= j1 { j2 + 1 } else { j2 }))
+            }
+            5 => {
+                let j = rng.gen_range(0..n_features);
+                let mut members: Vec<usize> = (0..n_items).filter(|&i| z.get(i, j)).collect();
+                if members.len() < 2 {
+                    return None;
+                }
+                members.shuffle(rng);
+                members.truncate(rng.gen_range(1..members.len()));
+                Some(ColumnMove::Split(j, members))
+            }
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, column_move: ColumnMove, views: &[FeatureAllocation], a: f64) {
+        let z = &mut self.z;
+        let weight_matrices = &mut self.weight_matrices[..];
+        match column_move {
+            ColumnMove::Add(column) => push_column(z, weight_matrices, a, &column, views),
+            ColumnMove::Delete(j) => swap_remove_column(z, weight_matrices, j),
+            ColumnMove::Merge(j1, j2) => {
+                for i in 0..z.n_items() {
+                    if z.get(i, j2) && !z.get(i, j1) {
+                        flip_bit(z, weight_matrices, a, [i, j1], views);
+                    }
+                }
+                swap_remove_column(z, weight_matrices, j2);
+            }
+            ColumnMove::Split(j, members) => {
+                let column: Vec<bool> = (0..z.n_items()).map(|i| members.contains(&i)).collect();
+                push_column(z, weight_matrices, a, &column, views);
+                for i in members {
+                    flip_bit(z, weight_matrices, a, [i, j], views);
+                }
+            }
+        }
+    }
+
+    /// Records the current expected loss in the trace, if there is one.
+    fn record(&mut self, iteration: usize, seconds: f64) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TracePoint {
+                iteration,
+                expected_loss: self.loss,
+                seconds,
+            });
+        }
+    }
+
+    fn result(&self) -> SweetResult {
+        SweetResult {
+            estimate: self
+                .best_z
+                .as_ref()
+                .unwrap_or(&self.z)
+                .without_empty_columns(),
+            expected_loss: self.best_loss,
+            iteration: self.when,
+            n_accepts: self.n_accepts,
+            n_rejects: self.n_rejects,
+            trace: self.trace.clone().unwrap_or_default(),
+        }
+    }
+
+    fn cool(&mut self, schedule: &Schedule, iteration: usize, n_iterations: usize) {
+        const WINDOW: usize = 100;
+        match *schedule {
+            Schedule::Geometric { rate, .. } => self.temperature *= rate,
+            Schedule::Linear { temperature } => {
+                self.temperature =
+                    (temperature * (1.0 - iteration as f64 / n_iterations as f64)).max(0.0)
+            }
+            Schedule::Adaptive { target, .. } => {
+                if iteration % WINDOW == 0 {
+                    let n_accepts_in_window = self.n_accepts - self.n_accepts_before_window;
+                    self.n_accepts_before_window = self.n_accepts;
+                    if n_accepts_in_window as f64 / WINDOW as f64 > target {
+                        self.temperature *= 0.9;
+                    } else {
+                        self.temperature /= 0.9;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs `work` on another thread while polling `monitor` on this thread, cancelling
+/// `cancellation` once the user interrupts or `timer` reaches `deadline` seconds, which may be
+/// before `work` starts.  Status messages which `work` sends are printed by `monitor`.  Also
+/// returns whether the user interrupted.
+fn watch<T: Send>(
+    work: impl FnOnce(Sender<String>) -> T + Send,
+    cancellation: &Cancellation,
+    deadline: f64,
+    timer: &EchoTimer,
+    monitor: &mut impl Monitor,
+) -> (T, bool) {
+    let mut interrupted = false;
+    let (sender, receiver) = std::sync::mpsc::channel::<String>();
+    let mut poll = || {
+        for msg in receiver.try_iter() {
+            if monitor.print(&msg) {
+                interrupted = true;
+                cancellation.cancel();
+            }
+        }
+        if !cancellation.is_cancelled() {
+            if monitor.interrupted() {
+                interrupted = true;
+                cancellation.cancel();
+            } else if timer.total_as_secs_f64() >= deadline {
+                cancellation.cancel();
+            }
+        }
+    };
+    poll();
+    let caller = std::thread::current();
+    let result = std::thread::scope(|scope| {
+        let handle = scope.spawn(move || {
+            let result = work(sender);
+            caller.unpark();
+            result
+        });
+        while !handle.is_finished() {
+            std::thread::park_timeout(std::time::Duration::from_millis(10));
+            poll();
+        }
+        let result = handle.join().unwrap();
+        poll();
+        result
+    });
+    (result, interrupted)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn neighborhood_sweeten(
+    z: &mut FeatureAllocation,
+    weight_matrices: &mut [WeightMatrix],
+    solver_states: &mut Vec<SolverState>,
+    views: &[FeatureAllocation],
+    weights: &[f64],
+    n_items: usize,
+    a: f64,
+    cancellation: &Cancellation,
+    pool: &ThreadPool,
+    timer: &EchoTimer,
+    messages: &Sender<String>,
+) -> (f64, bool) {
+    let mut outer_loss = expected_loss_from_solver_states(weight_matrices, solver_states, weights);
+    loop {
+        if timer.echo() {
+            let _ = messages.send(format!("Current loss: {}\n", outer_loss));
+        }
+        if cancellation.is_cancelled() {
+            return (outer_loss, true);
+        }
+        // Optimize within a given number of columns
+        let mut best_candidate_loss = f64::INFINITY;
+        let mut best_index = [0, 0];
+        for i in 0..n_items {
+            for j in 0..z.n_features() {
+                let Some(candidate_loss) = expected_loss_from_weight_matrices_if_flip_bit(
+                    z,
+                    weight_matrices,
+                    solver_states,
+                    a,
+                    [i, j],
+                    views,
+                    weights,
+                    cancellation,
+                    pool,
+                ) else {
+                    return (outer_loss, true);
+                };
+                if candidate_loss < best_candidate_loss {
+                    best_index = [i, j];
+                    best_candidate_loss = candidate_loss;
+                }
+            }
+        }
+        if best_candidate_loss < outer_loss {
+            flip_bit(z, weight_matrices, a, best_index, views);
+            let Some(states) = update_solver_states(
+                weight_matrices,
+                solver_states,
+                best_index[1],
+                cancellation,
+                pool,
+            ) else {
+                flip_bit(z, weight_matrices, a, best_index, views);
+                return (outer_loss, true);
+            };
+            *solver_states = states;
+            outer_loss = best_candidate_loss;
+        } else {
+            return (outer_loss, false);
+        }
+    }
+}
+
+/// The number of multisets of size `k` from `n` elements, saturating on overflow.
+fn n_combinations(n: u128, k: usize) -> u128 {
+    (0..k as u128).fold(1_u128, |x, i| x.saturating_mul(n + i) / (i + 1))
+}
+
+/// The feature allocation without empty features, except that it has one (empty) feature if all
+/// are empty.
+fn with_a_feature(z: &FeatureAllocation) -> FeatureAllocation {
+    let z = z.without_empty_columns();
+    if z.n_features() == 0 {
+        FeatureAllocation::zeros(z.n_items(), 1)
+    } else {
+        z
+    }
+}
+
+fn index_1d_to_2d(index: usize, ncols: usize) -> [usize; 2] {
+    [index / ncols, index % ncols]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Samples {
+        let truth = FeatureAllocation::from_fn(6, 2, |i, j| (i + j) % 3 == 0);
+        let mut noisy = truth.clone();
+        noisy.flip(5, 1);
+        Samples::new(vec![truth.clone(), noisy, truth.clone(), truth]).unwrap()
+    }
+
+    #[test]
+    fn all_algorithms_recover_the_mode() {
+        let samples = samples();
+        let truth = samples.get(0).unwrap().clone();
+        let faro = FaroLoss::new(1.0).unwrap();
+        for algorithm in [
+            Algorithm::Stochastic,
+            Algorithm::Annealing,
+            Algorithm::Deterministic,
+            Algorithm::Draws,
+            Algorithm::DoubleGreedy,
+            Algorithm::Exhaustive,
+        ] {
+            let result = Fangs::new()
+                .n_init(4)
+                .n_sweet(2)
+                .n_iterations(200)
+                .n_cores(1)
+                .algorithm(algorithm)
+                .quiet(true)
+                .seed(1)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            assert_eq!(result.estimate.n_items(), 6);
+            assert_eq!(faro.loss(&result.estimate, &truth).unwrap(), 0.0);
+            assert_eq!(result.expected_loss, 0.25);
+        }
+    }
+
+    #[test]
+    fn all_solvers_recover_the_mode() {
+        let samples = samples();
+        let truth = samples.get(0).unwrap().clone();
+        for solver in [
+            Solver::Lapjv,
+            Solver::Hungarian,
+            Solver::Auction,
+            Solver::Exhaustive,
+        ] {
+            let faro = FaroLoss::new(1.0).unwrap().with_solver(solver);
+            let result = Fangs::new()
+                .loss(faro)
+                .n_init(4)
+                .n_sweet(2)
+                .n_iterations(200)
+                .n_cores(1)
+                .quiet(true)
+                .seed(1)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            assert_eq!(faro.loss(&result.estimate, &truth).unwrap(), 0.0);
+            assert_eq!(result.expected_loss, 0.25);
+        }
+    }
+
+    #[test]
+    fn mini_batches_give_exact_expected_losses() {
+        let truth = FeatureAllocation::from_fn(8, 3, |i, j| (i + j) % 3 == 0);
+        let samples: Vec<_> = (0..40)
+            .map(|k| {
+                let mut z = truth.clone();
+                z.flip(k % 8, k % 3);
+                z.flip(5 * k % 8, (k + 1) % 3);
+                z
+            })
+            .collect();
+        let samples = Samples::new(samples).unwrap();
+        let faro = FaroLoss::new(1.0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        // Column moves are scored on mini-batches too.
+        for column_moves in [0.0, 0.3] {
+            let result = Fangs::new()
+                .n_iterations(500)
+                .n_cores(2)
+                .column_moves(column_moves)
+                .mini_batch(4, 25)
+                .trace(true)
+                .quiet(true)
+                .seed(3)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            for sweet in &result.sweets {
+                approx::assert_ulps_eq!(
+                    sweet.expected_loss,
+                    faro.expected_loss(&sweet.estimate, &samples, &pool)
+                        .unwrap()
+                );
+                assert!(sweet
+                    .trace
+                    .iter()
+                    .skip(1)
+                    .all(|x| x.iteration % 25 == 0 || x.iteration == 500));
+            }
+            assert!(result.expected_loss <= faro.expected_loss(&truth, &samples, &pool).unwrap());
+        }
+    }
+
+    #[test]
+    fn samples_without_features_give_no_loss() {
+        let samples = Samples::new(vec![FeatureAllocation::zeros(2, 0); 2]).unwrap();
+        for algorithm in [
+            Algorithm::Stochastic,
+            Algorithm::Annealing,
+            Algorithm::Deterministic,
+            Algorithm::Draws,
+            Algorithm::DoubleGreedy,
+            Algorithm::Exhaustive,
+        ] {
+            let result = Fangs::new()
+                .n_iterations(10)
+                .n_cores(1)
+                .algorithm(algorithm)
+                .quiet(true)
+                .seed(1)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            assert_eq!(result.expected_loss, 0.0);
+        }
+        let mut search = Fangs::new()
+            .n_cores(1)
+            .quiet(true)
+            .seed(1)
+            .start(samples, &mut Silent)
+            .unwrap();
+        search
+            .add_samples(vec![FeatureAllocation::zeros(2, 0)], vec![1.0])
+            .unwrap();
+        assert_eq!(search.run(10, &mut Silent).unwrap().expected_loss, 0.0);
+    }
+
+    #[test]
+    fn exhaustive_search_finds_the_minimizer() {
+        let samples = Samples::new(vec![
+            FeatureAllocation::from_fn(4, 2, |i, j| i <= j + 1),
+            FeatureAllocation::from_fn(4, 1, |i, _| i % 2 == 0),
+            FeatureAllocation::from_fn(4, 3, |i, j| i == j),
+            FeatureAllocation::from_fn(4, 2, |i, j| i + j == 3),
+        ])
+        .unwrap();
+        let faro = FaroLoss::new(0.6).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let fangs = Fangs::new()
+            .loss(faro)
+            .n_cores(2)
+            .algorithm(Algorithm::Exhaustive)
+            .max_features(2)
+            .quiet(true);
+        let result = fangs.run(&samples, &mut Silent).unwrap();
+        // The number of multisets of two of the 16 possible features.
+        assert_eq!(result.n_iterations, 136);
+        approx::assert_ulps_eq!(
+            result.expected_loss,
+            faro.expected_loss(&result.estimate, &samples, &pool)
+                .unwrap()
+        );
+        let minimum = (0..1_u32 << 8)
+            .map(|bits| {
+                let z = FeatureAllocation::from_fn(4, 2, |i, j| bits >> (2 * i + j) & 1 == 1);
+                faro.expected_loss(&z, &samples, &pool).unwrap()
+            })
+            .fold(f64::INFINITY, f64::min);
+        approx::assert_ulps_eq!(result.expected_loss, minimum);
+        let result = fangs.max_features(0).run(&samples, &mut Silent).unwrap();
+        assert!(result.expected_loss <= minimum);
+        assert_eq!(
+            Fangs::new()
+                .algorithm(Algorithm::Exhaustive)
+                .max_features(100)
+                .run(&samples, &mut Silent)
+                .unwrap_err(),
+            Error::TooLarge
+        );
+    }
+
+    #[test]
+    fn trace_records_every_sweet() {
+        let samples = samples();
+        for algorithm in [Algorithm::Stochastic, Algorithm::Deterministic] {
+            let result = Fangs::new()
+                .n_init(2)
+                .n_sweet(2)
+                .n_iterations(50)
+                .column_moves(0.5)
+                .algorithm(algorithm)
+                .trace(true)
+                .quiet(true)
+                .seed(2)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            assert_eq!(result.sweets.len(), 2);
+            let best = &result.sweets[result.which_sweet];
+            assert_eq!(best.expected_loss, result.expected_loss);
+            assert_eq!(best.estimate, result.estimate);
+            for sweet in &result.sweets {
+                assert_eq!(sweet.trace[0].iteration, 0);
+                assert_eq!(
+                    sweet.trace.last().unwrap().expected_loss,
+                    sweet.expected_loss
+                );
+                assert!(sweet.trace.windows(2).all(|x| x[0].seconds <= x[1].seconds));
+                if algorithm == Algorithm::Stochastic {
+                    assert_eq!(sweet.trace.len(), sweet.n_accepts + 1);
+                    assert!(sweet
+                        .trace
+                        .windows(2)
+                        .all(|x| x[0].expected_loss > x[1].expected_loss));
+                }
+            }
+        }
+        assert!(Fangs::new()
+            .run(&samples, &mut Silent)
+            .unwrap()
+            .sweets
+            .is_empty());
+    }
+
+    #[test]
+    fn time_limit_truncates_every_phase() {
+        let samples = samples();
+        let faro = FaroLoss::new(1.0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        for algorithm in [Algorithm::Stochastic, Algorithm::Deterministic] {
+            let search = Fangs::new()
+                .n_init(4)
+                .n_sweet(2)
+                .n_iterations(100)
+                .algorithm(algorithm)
+                .quiet(true)
+                .seed(4);
+            let result = search.run(&samples, &mut Silent).unwrap();
+            assert_eq!(result.truncated, Truncated::default());
+            assert!(
+                (result.seconds_alignment + result.seconds_ranking - result.seconds_initialization)
+                    .abs()
+                    < 1e-9
+            );
+            // Without time, one initial estimate is obtained, ranked, and returned unchanged.
+            let result = search.max_seconds(0.0).run(&samples, &mut Silent).unwrap();
+            assert_eq!(
+                result.truncated,
+                Truncated {
+                    alignment: true,
+                    ranking: false,
+                    sweetening: true,
+                }
+            );
+            assert_eq!(result.n_iterations, 0);
+            assert_eq!(result.n_accepts, 0);
+            let expected_loss = faro
+                .expected_loss(&result.estimate, &samples, &pool)
+                .unwrap();
+            assert!((result.expected_loss - expected_loss).abs() < 1e-12);
+        }
+        // Initial estimates which need no alignment may still be left out of the ranking.
+        let result = Fangs::new()
+            .n_init(1)
+            .initial_estimates(vec![samples.get(1).unwrap().clone()])
+            .max_seconds(0.0)
+            .quiet(true)
+            .run(&samples, &mut Silent)
+            .unwrap();
+        assert!(result.truncated.ranking);
+        let result = Fangs::new()
+            .algorithm(Algorithm::DoubleGreedy)
+            .max_seconds(0.0)
+            .run(&samples, &mut Silent)
+            .unwrap();
+        assert!(result.truncated.sweetening);
+    }
+
+    #[test]
+    fn draws_are_ranked_and_sweetened() {
+        let truth = FeatureAllocation::from_fn(8, 3, |i, j| (i + j) % 3 == 0);
+        let samples: Vec<_> = (0..12)
+            .map(|k| {
+                let mut z = truth.clone();
+                z.flip(k % 8, k % 3);
+                z.flip((3 * k + 1) % 8, (k + 1) % 3);
+                z
+            })
+            .collect();
+        let samples = Samples::new(samples).unwrap();
+        let result = Fangs::new()
+            .n_sweet(3)
+            .n_iterations(100)
+            .algorithm(Algorithm::Draws)
+            .trace(true)
+            .quiet(true)
+            .seed(3)
+            .run(&samples, &mut Silent)
+            .unwrap();
+        assert_eq!(result.n_iterations, 100);
+        assert_eq!(result.sweets.len(), 3);
+        assert!(result.sweets.iter().any(|sweet| sweet.n_accepts > 0));
+        // The sweetened draws are those with the lowest expected loss, in order.
+        assert!(result
+            .sweets
+            .windows(2)
+            .all(|x| x[0].trace[0].expected_loss <= x[1].trace[0].expected_loss));
+        for sweet in &result.sweets {
+            assert!(sweet.expected_loss <= sweet.trace[0].expected_loss);
+        }
+        let faro = FaroLoss::new(1.0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let expected_loss = faro
+            .expected_loss(&result.estimate, &samples, &pool)
+            .unwrap();
+        assert!((result.expected_loss - expected_loss).abs() < 1e-12);
+        // The draws are candidates as they are, so the result is never worse than the best draw.
+        let best_draw = samples
+            .as_slice()
+            .iter()
+            .map(|z| faro.expected_loss(z, &samples, &pool).unwrap())
+            .fold(f64::INFINITY, f64::min);
+        for seed in 0..5 {
+            let result = Fangs::new()
+                .n_sweet(1)
+                .n_iterations(1)
+                .algorithm(Algorithm::Draws)
+                .quiet(true)
+                .seed(seed)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            assert!(result.expected_loss <= best_draw + 1e-12);
+        }
+    }
+
+    #[test]
+    fn initial_estimates_join_the_candidates() {
+        let samples = samples();
+        let truth = samples.get(0).unwrap();
+        let mut initial = truth.clone();
+        initial.push_column(&[false; 6]);
+        let result = Fangs::new()
+            .n_init(1)
+            .n_sweet(2)
+            .n_iterations(10)
+            .initial_estimates(vec![initial.clone(), FeatureAllocation::zeros(6, 0)])
+            .trace(true)
+            .quiet(true)
+            .seed(3)
+            .run(&samples, &mut Silent)
+            .unwrap();
+        assert_eq!(result.sweets.len(), 2);
+        assert_eq!(result.sweets[0].trace[0].expected_loss, 0.25);
+        let result = Fangs::new()
+            .algorithm(Algorithm::DoubleGreedy)
+            .initial_estimates(vec![FeatureAllocation::zeros(6, 1), initial])
+            .run(&samples, &mut Silent)
+            .unwrap();
+        assert_eq!(&result.estimate, truth);
+        assert_eq!(
+            Fangs::new()
+                .initial_estimates(vec![FeatureAllocation::zeros(5, 1)])
+                .run(&samples, &mut Silent)
+                .unwrap_err(),
+            Error::InconsistentItems
+        );
+    }
+
+    /// A monitor which interrupts once it has been asked the given number of times.
+    struct InterruptAfter(usize);
+
+    impl Monitor for InterruptAfter {
+        fn print(&mut self, _msg: &str) -> bool {
+            false
+        }
+
+        fn interrupted(&mut self) -> bool {
+            self.0 = self.0.saturating_sub(1);
+            self.0 == 0
+        }
+    }
+
+    /// Interrupts once a file exists.
+    struct InterruptOnceExists(PathBuf);
+
+    impl Monitor for InterruptOnceExists {
+        fn print(&mut self, _msg: &str) -> bool {
+            false
+        }
+
+        fn interrupted(&mut self) -> bool {
+            self.0.exists()
+        }
+    }
+
+    #[test]
+    fn interrupts_are_honored_within_computations() {
+        let samples = samples();
+        let search = Fangs::new().n_init(4).n_sweet(2).quiet(true).seed(4);
+        for algorithm in [Algorithm::Stochastic, Algorithm::Deterministic] {
+            assert_eq!(
+                search
+                    .clone()
+                    .algorithm(algorithm)
+                    .run(&samples, &mut InterruptAfter(1))
+                    .unwrap_err(),
+                Error::Interrupted
+            );
+        }
+        // The double greedy search starts from an estimate, which is returned as is.
+        let result = search
+            .algorithm(Algorithm::DoubleGreedy)
+            .run(&samples, &mut InterruptAfter(1))
+            .unwrap();
+        assert_eq!(result.estimate, FeatureAllocation::zeros(6, 2));
+        assert!(!result.truncated.sweetening);
+    }
+
+    #[test]
+    fn resumed_search_matches_uninterrupted_search() {
+        let samples = samples();
+        let path = std::env::temp_dir().join(format!("fangs-{}.checkpoint", std::process::id()));
+        for algorithm in [Algorithm::Annealing, Algorithm::Draws] {
+            let fangs = Fangs::new()
+                .n_init(4)
+                .n_sweet(2)
+                .n_iterations(1000)
+                .column_moves(0.3)
+                .algorithm(algorithm)
+                .trace(true)
+                .quiet(true)
+                .seed(4);
+            let expected = fangs.clone().run(&samples, &mut Silent).unwrap();
+            // The interrupt comes shortly after the first checkpoint is written, wherever the
+            // search is at that time.
+            let fangs = fangs.checkpoint(&path, 0.0);
+            let _ = std::fs::remove_file(&path);
+            let stopped = fangs
+                .run(&samples, &mut InterruptOnceExists(path.clone()))
+                .unwrap();
+            assert!(stopped.n_iterations < 1000);
+            let fangs = fangs.checkpoint(&path, f64::INFINITY);
+            let result = fangs.resume(&path, &samples, &mut Silent).unwrap();
+            assert_eq!(result.n_iterations, 1000);
+            assert_eq!(result.seed, expected.seed);
+            assert_eq!(result.estimate, expected.estimate);
+            assert_eq!(result.expected_loss, expected.expected_loss);
+            assert_eq!(result.iteration, expected.iteration);
+            assert_eq!(result.n_accepts, expected.n_accepts);
+            assert_eq!(result.n_rejects, expected.n_rejects);
+            for (x, y) in result.sweets.iter().zip(&expected.sweets) {
+                assert_eq!(x.estimate, y.estimate);
+                let losses = |sweet: &SweetResult| {
+                    sweet
+                        .trace
+                        .iter()
+                        .map(|x| (x.iteration, x.expected_loss))
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(losses(x), losses(y));
+            }
+            let other = Samples::new(vec![samples.get(1).unwrap().clone()]).unwrap();
+            assert_eq!(
+                fangs.resume(&path, &other, &mut Silent).unwrap_err(),
+                Error::CheckpointMismatch
+            );
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(
+                fangs.resume(&path, &samples, &mut Silent).unwrap_err(),
+                Error::Checkpoint
+            );
+        }
+    }
+
+    #[test]
+    fn search_continues_in_steps() {
+        let samples = samples();
+        let fangs = Fangs::new()
+            .n_init(4)
+            .n_sweet(2)
+            .n_iterations(300)
+            .column_moves(0.3)
+            .quiet(true)
+            .seed(5);
+        let expected = fangs.run(&samples, &mut Silent).unwrap();
+        let mut search = fangs.start(samples.clone(), &mut Silent).unwrap();
+        assert_eq!(search.iteration(), 0);
+        search.run(100, &mut Silent).unwrap();
+        let result = search.run(200, &mut Silent).unwrap();
+        assert_eq!(search.iteration(), 300);
+        assert_eq!(result.estimate, expected.estimate);
+        assert_eq!(result.expected_loss, expected.expected_loss);
+        assert_eq!(result.n_accepts, expected.n_accepts);
+        assert_eq!(search.result().estimate, expected.estimate);
+        let noisy = samples.get(1).unwrap().clone();
+        search
+            .add_samples(vec![noisy.clone(), noisy], vec![1.0, 2.0])
+            .unwrap();
+        assert_eq!(search.samples().len(), 6);
+        let faro = FaroLoss::new(1.0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new().build().unwrap();
+        let estimate = search.result().estimate;
+        let expected_loss = faro
+            .expected_loss(&estimate, search.samples(), &pool)
+            .unwrap();
+        assert!((search.expected_loss() - expected_loss).abs() < 1e-12);
+        search.run(100, &mut Silent).unwrap();
+        assert_eq!(search.iteration(), 400);
+        assert_eq!(
+            Fangs::new()
+                .algorithm(Algorithm::Draws)
+                .start(samples, &mut Silent)
+                .err(),
+            Some(Error::InvalidAlgorithm)
+        );
+    }
+
+    #[test]
+    fn column_moves_change_the_number_of_features() {
+        let truth = FeatureAllocation::from_fn(8, 3, |i, j| (i + j) % 3 == 0 || i == 2 * j);
+        let samples = Samples::new(vec![truth.clone(); 3]).unwrap();
+        let views = samples.as_slice();
+        let weights = samples.weights();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let cancelled = Cancellation::default();
+        cancelled.cancel();
+        let sweeten = |column_moves, mini_batch, abandon| {
+            let z = FeatureAllocation::zeros(8, 1);
+            let cancellation = Cancellation::default();
+            let weight_matrices =
+                make_weight_matrices(&z, views, 1.0, Solver::default(), &cancellation, &pool)
+                    .unwrap();
+            let solver_states = make_solver_states(&weight_matrices, &cancellation, &pool).unwrap();
+            let loss = expected_loss_from_solver_states(&weight_matrices, &solver_states, weights);
+            let mut sweet = Sweet {
+                z,
+                loss,
+                weight_matrices,
+                solver_states,
+                stale: vec![Vec::new(); views.len()],
+                id: 0,
+                best_z: None,
+                best_loss: loss,
+                when: 0,
+                n_accepts: 0,
+                n_rejects: 0,
+                temperature: 0.0,
+                n_accepts_before_window: 0,
+                rng: Pcg64Mcg::seed_from_u64(5),
+                trace: None,
+                behind: false,
+            };
+            let mut n_abandoned = 0;
+            for iteration in 1..=2000 {
+                let mut propose = |cancellation| {
+                    sweet.propose(
+                        views,
+                        weights,
+                        1.0,
+                        cancellation,
+                        &pool,
+                        iteration,
+                        column_moves,
+                        mini_batch,
+                    )
+                };
+                // An abandoned proposal leaves no trace, so the same proposal is made next.  Small
+                // problems may be solved before the cancellation is noticed.
+                let completed = abandon && propose(&cancelled);
+                if abandon && !completed {
+                    n_abandoned += 1;
+                }
+                if !completed {
+                    assert!(propose(&cancellation));
+                }
+            }
+            assert_eq!(n_abandoned > 0, abandon);
+            sweet
+        };
+        assert_eq!(sweeten(0.0, None, false).z.n_features(), 1);
+        let sweet = sweeten(0.2, None, false);
+        assert_eq!(sweet.best_loss, 0.0);
+        assert_eq!(sweet.z.without_empty_columns().n_features(), 3);
+        for mini_batch in [None, Some((1, 10))] {
+            let x = sweeten(0.2, mini_batch, false);
+            let y = sweeten(0.2, mini_batch, true);
+            assert_eq!(x.z, y.z);
+            assert_eq!(x.loss, y.loss);
+            assert_eq!((x.n_accepts, x.n_rejects), (y.n_accepts, y.n_rejects));
+        }
+        assert_eq!(
+            Fangs::new()
+                .column_moves(1.5)
+                .run(&samples, &mut Silent)
+                .unwrap_err(),
+            Error::InvalidProbability
+        );
+    }
+
+    #[test]
+    fn annealing_counts_proposals_and_keeps_the_best() {
+        let samples = samples();
+        for schedule in [
+            Schedule::Geometric {
+                temperature: 2.0,
+                rate: 0.99,
+            },
+            Schedule::Linear { temperature: 2.0 },
+            Schedule::Adaptive {
+                temperature: 2.0,
+                target: 0.2,
+            },
+        ] {
+            let result = Fangs::new()
+                .n_sweet(1)
+                .n_iterations(300)
+                .n_cores(1)
+                .algorithm(Algorithm::Annealing)
+                .schedule(schedule)
+                .quiet(true)
+                .seed(3)
+                .run(&samples, &mut Silent)
+                .unwrap();
+            assert_eq!(result.n_accepts + result.n_rejects, 300);
+            assert_eq!(result.expected_loss, 0.25);
+        }
+        let invalid = Fangs::new()
+            .algorithm(Algorithm::Annealing)
+            .schedule(Schedule::Adaptive {
+                temperature: 1.0,
+                target: 1.5,
+            })
+            .run(&samples, &mut Silent);
+        assert_eq!(invalid.unwrap_err(), Error::InvalidSchedule);
+    }
+
+    #[test]
+    fn seed_determines_result_regardless_of_threads() {
+        let mut rng = Pcg64Mcg::seed_from_u64(7);
+        let samples = Samples::new(
+            (0..20)
+                .map(|_| {
+                    let n_features = rng.gen_range(1..5);
+                    let bits: Vec<bool> = (0..8 * n_features).map(|_| rng.gen_bool(0.4)).collect();
+                    FeatureAllocation::from_fn(8, n_features, |i, j| bits[i * n_features + j])
+                })
+                .collect(),
+        )
+        .unwrap();
+        for algorithm in [Algorithm::Stochastic, Algorithm::Draws] {
+            let run = |n_cores| {
+                Fangs::new()
+                    .n_init(6)
+                    .n_iterations(100)
+                    .n_cores(n_cores)
+                    .algorithm(algorithm)
+                    .quiet(true)
+                    .seed(42)
+                    .run(&samples, &mut Silent)
+                    .unwrap()
+            };
+            let (r1, r2) = (run(1), run(3));
+            assert_eq!(r1.estimate, r2.estimate);
+            assert_eq!(r1.expected_loss, r2.expected_loss);
+            assert_eq!(r1.iteration, r2.iteration);
+            assert_eq!(r1.which_sweet, r2.which_sweet);
+            assert_eq!(r1.seed, 42);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::loss::{losses_from_samples, Cancellation};
+use crate::{Error, FaroLoss, FeatureAllocation};
+use rayon::ThreadPool;
+
+/// The precision of the Monte Carlo estimate of the expected loss, where the (weighted) mean is
+/// treated as a ratio estimator and its variance is found by the delta method.
+///
+/// The naive standard error assumes independent samples, whereas the batch means standard error
+/// allows for autocorrelated samples, e.g., from Markov chain Monte Carlo, by dividing the samples
+/// (in their original order) into about the square root of their number of contiguous batches.
+/// Quantities which cannot be computed from too few samples are `NaN`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StandardError {
+    /// The Monte Carlo estimate of the expected loss.
+    pub expected_loss: f64,
+    /// The (weighted) variance of the losses to the samples.
+    pub variance: f64,
+    /// The standard error of the expected loss assuming independent samples.
+    pub standard_error: f64,
+    /// The standard error of the expected loss by the method of batch means.
+    pub batch_means_standard_error: f64,
+    /// The number of independent samples giving the batch means standard error.
+    pub effective_sample_size: f64,
+    /// The number of batches used for the batch means standard error.
+    pub n_batches: usize,
+}
+
+impl FaroLoss {
+    /// The Monte Carlo estimate of the expected loss of `z` and its precision based on posterior
+    /// `samples` with importance `weights`.  Unlike [`Samples`](crate::Samples), the samples are
+    /// not deduplicated, since their order matters for the batch means standard error.
+    pub fn expected_loss_standard_error(
+        &self,
+        z: &FeatureAllocation,
+        samples: &[FeatureAllocation],
+        weights: &[f64],
+        pool: &ThreadPool,
+    ) -> Result<StandardError, Error> {
+        if samples.is_empty() {
+            return Err(Error::NoSamples);
+        }
+        if samples.iter().any(|zz| zz.n_items() != z.n_items()) {
+            return Err(Error::InconsistentItems);
+        }
+        if weights.len() != samples.len()
+            || weights.iter().any(|w| !w.is_finite() || *w < 0.0)
+            || weights.iter().all(|&w| w == 0.0)
+        {
+            return Err(Error::InvalidWeights);
+        }
+        self.check_size(
+            samples
+                .iter()
+                .fold(z.n_features(), |n, zz| n.max(zz.n_features())),
+        )?;
+        let losses = losses_from_samples(
+            z,
+            samples,
+            self.a(),
+            self.solver(),
+            &Cancellation::default(),
+            pool,
+        )
+        .unwrap();
+        Ok(standard_error(&losses, weights))
+    }
+}
+
+fn standard_error(losses: &[f64], weights: &[f64]) -> StandardError {
+    let n = losses.len();
+    let total: f64 = weights.iter().sum();
+    let expected_loss = losses.iter().zip(weights).map(|(x, w)| w * x).sum::<f64>() / total;
+    // The terms of the linearized ratio estimator, which sum to zero.
+    let residuals: Vec<f64> = losses
+        .iter()
+        .zip(weights)
+        .map(|(x, w)| w * (x - expected_loss))
+        .collect();
+    let variance = residuals
+        .iter()
+        .zip(losses)
+        .map(|(r, x)| r * (x - expected_loss))
+        .sum::<f64>()
+        / total;
+    let sum_of_squares: f64 = residuals.iter().map(|r| r * r).sum();
+    let standard_error = if n > 1 {
+        (n as f64 / (n as f64 - 1.0) * sum_of_squares).sqrt() / total
+    } else {
+        f64::NAN
+    };
+    let n_batches = (n as f64).sqrt() as usize;
+    let batch_means_standard_error = if n_batches > 1 {
+        let mut sums = vec![0.0; n_batches];
+        for (k, r) in residuals.iter().enumerate() {
+            sums[k * n_batches / n] += r;
+        }
+        let sum_of_squares: f64 = sums.iter().map(|s| s * s).sum();
+        (n_batches as f64 / (n_batches as f64 - 1.0) * sum_of_squares).sqrt() / total
+    } else {
+        f64::NAN
+    };
+    StandardError {
+        expected_loss,
+        variance,
+        standard_error,
+        batch_means_standard_error,
+        effective_sample_size: variance / (batch_means_standard_error * batch_means_standard_error),
+        n_batches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_errors_agree_with_textbook_formulas() {
+        let losses = [1.0, 2.0, 4.0, 3.0, 5.0, 6.0, 8.0, 7.0, 9.0];
+        let x = standard_error(&losses, &[1.0; 9]);
+        approx::assert_ulps_eq!(x.expected_loss, 5.0);
+        approx::assert_ulps_eq!(x.variance, 60.0 / 9.0);
+        approx::assert_ulps_eq!(x.standard_error, (60.0 / 8.0 / 9.0_f64).sqrt());
+        // The batch means are 7/3, 14/3, and 8, so their variance is 73/9.
+        assert_eq!(x.n_batches, 3);
+        approx::assert_ulps_eq!(x.batch_means_standard_error, (73.0 / 9.0 / 3.0_f64).sqrt());
+        approx::assert_ulps_eq!(x.effective_sample_size, 180.0 / 73.0);
+        // Integer weights are the same as repeating the samples, aside from the sample size.
+        let y = standard_error(&[1.0, 3.0], &[3.0, 1.0]);
+        approx::assert_ulps_eq!(y.expected_loss, 1.5);
+        approx::assert_ulps_eq!(y.variance, 0.75);
+        assert!(y.batch_means_standard_error.is_nan());
+        let faro = FaroLoss::new(1.0).unwrap();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let z = FeatureAllocation::zeros(3, 0);
+        let samples = vec![z.clone(), FeatureAllocation::from_fn(3, 1, |i, _| i == 0)];
+        assert_eq!(
+            faro.expected_loss_standard_error(&z, &samples, &[1.0], &pool),
+            Err(Error::InvalidWeights)
+        );
+        let x = faro
+            .expected_loss_standard_error(&z, &samples, &[1.0, 1.0], &pool)
+            .unwrap();
+        assert_eq!(x.expected_loss, 0.5);
+        assert_eq!(x.standard_error, 0.5);
+    }
+}
@@ -52,6 +52,7 @@ impl std::error::Error for LapJVError {}
 
 pub struct LapJV<'a, T: 'a> {
     costs: &'a Matrix<T>,
+    unmatched: Option<(&'a [T], &'a [T])>,
     dim: usize,
     free_rows: Vec<usize>,
     v: Vec<T>,
@@ -72,6 +73,89 @@ where
     LapJV::new(costs).solve()
 }
 
+/// Solve LAP problem given cost matrix, also returning the state needed to quickly re-solve
+/// after some rows of the cost matrix change (see `lapjv_update`)
+pub fn lapjv_with_state<T>(costs: &Matrix<T>) -> Result<LapJVState<T>, LapJVError>
+where
+    T: LapJVCost,
+{
+    LapJV::new(costs).solve_with_state()
+}
+
+/// Re-solve LAP problem after the given rows of the cost matrix changed, warm-starting from the
+/// column duals and assignment in `state`, which was obtained from the cost matrix before the
+/// change.  Each changed row needs only one augmenting path.  If the dimensions of the cost
+/// matrix changed, the problem is solved from scratch.
+pub fn lapjv_update<T>(
+    costs: &Matrix<T>,
+    state: &mut LapJVState<T>,
+    rows: &[usize],
+) -> Result<(), LapJVError>
+where
+    T: LapJVCost,
+{
+    lapjv_update_with_cancellation(costs, state, rows, &Cancellation::default())
+}
+
+/// Like `lapjv_update`, but stops early if `cancellation` is cancelled, in which case `state` is
+/// left empty (so that the next update solves the problem from scratch)
+pub fn lapjv_update_with_cancellation<T>(
+    costs: &Matrix<T>,
+    state: &mut LapJVState<T>,
+    rows: &[usize],
+    cancellation: &Cancellation,
+) -> Result<(), LapJVError>
+where
+    T: LapJVCost,
+{
+    *state = LapJV::new(costs)
+        .with_cancellation(cancellation.clone())
+        .with_state(std::mem::take(state), rows)
+        .solve_with_state()?;
+    Ok(())
+}
+
+/// Column duals and assignment of a solved LAP problem
+#[derive(Debug, Clone)]
+pub struct LapJVState<T> {
+    v: Vec<T>,
+    in_row: Vec<usize>,
+    in_col: Vec<usize>,
+}
+
+impl<T> Default for LapJVState<T> {
+    fn default() -> Self {
+        Self {
+            v: Vec::new(),
+            in_row: Vec::new(),
+            in_col: Vec::new(),
+        }
+    }
+}
+
+impl<T> LapJVState<T> {
+    /// A state from the column duals and assignment of a previous solution, e.g., after saving
+    /// them to a file
+    pub fn from_parts(v: Vec<T>, in_row: Vec<usize>, in_col: Vec<usize>) -> Self {
+        Self { v, in_row, in_col }
+    }
+
+    /// The column assigned to each row
+    pub fn in_row(&self) -> &[usize] {
+        &self.in_row
+    }
+
+    /// The row assigned to each column
+    pub fn in_col(&self) -> &[usize] {
+        &self.in_col
+    }
+
+    /// The column duals
+    pub fn v(&self) -> &[T] {
+        &self.v
+    }
+}
+
 /// Calculate solution cost by a result row
 pub fn cost<T>(input: &Matrix<T>, row: &[usize]) -> T
 where
@@ -81,7 +165,32 @@ where
         .fold(T::zero(), |acc, i| acc + input[(i, row[i])])
 }
 
-#[derive(Clone)]
+/// Calculate solution cost of a rectangular LAP problem (see `LapJV::rectangular`) by a result
+/// row, which includes the rows beyond those of the cost matrix
+pub fn cost_rectangular<T>(
+    input: &Matrix<T>,
+    unmatched_rows: &[T],
+    unmatched_cols: &[T],
+    row: &[usize],
+) -> T
+where
+    T: LapJVCost,
+{
+    let (n_rows, n_cols) = input.dim();
+    (0..row.len()).fold(T::zero(), |acc, i| {
+        let j = row[i];
+        acc + match (i < n_rows, j < n_cols) {
+            (true, true) => input[(i, j)],
+            (true, false) => unmatched_rows[i],
+            (false, true) => unmatched_cols[j],
+            (false, false) => T::zero(),
+        }
+    })
+}
+
+/// A token which stops a solver early when cancelled, possibly from another thread.  Clones share
+/// the same state, so one token can stop many solvers.
+#[derive(Clone, Default)]
 pub struct Cancellation(Arc<AtomicBool>);
 
 impl Cancellation {
@@ -105,6 +214,24 @@ where
 {
     pub fn new(costs: &'a Matrix<T>) -> Self {
         let dim = costs.dim().0; // square matrix dimensions
+        Self::with_dim(costs, None, dim)
+    }
+
+    /// Solver for a rectangular cost matrix, in which every row is matched if there are fewer
+    /// rows than columns and every column is matched otherwise.  Row `i` left unmatched costs
+    /// `unmatched_rows[i]` and column `j` left unmatched costs `unmatched_cols[j]`.  In the
+    /// solution, rows and columns beyond those of the cost matrix stand for being unmatched, so
+    /// the assignment is of the larger dimension
+    pub fn rectangular(
+        costs: &'a Matrix<T>,
+        unmatched_rows: &'a [T],
+        unmatched_cols: &'a [T],
+    ) -> Self {
+        let (n_rows, n_cols) = costs.dim();
+        Self::with_dim(costs, Some((unmatched_rows, unmatched_cols)), n_rows.max(n_cols))
+    }
+
+    fn with_dim(costs: &'a Matrix<T>, unmatched: Option<(&'a [T], &'a [T])>, dim: usize) -> Self {
         let free_rows = Vec::with_capacity(dim); // list of unassigned rows.
         let v = Vec::with_capacity(dim);
         let in_row = vec![0; dim];
@@ -112,6 +239,7 @@ where
         let cancellation = Cancellation(Default::default());
         Self {
             costs,
+            unmatched,
             dim,
             free_rows,
             v,
@@ -121,11 +249,37 @@ where
         }
     }
 
+    /// Warm-starts the solver so that it only needs to reassign `rows`, given a `state` which
+    /// solved the problem before those rows of the cost matrix changed.  The state is ignored if
+    /// its dimension differs.
+    pub fn with_state(mut self, state: LapJVState<T>, rows: &[usize]) -> Self {
+        if state.v.len() != self.dim {
+            return self;
+        }
+        self.v = state.v;
+        self.in_row = state.in_row;
+        self.in_col = state.in_col;
+        for &i in rows {
+            let j = self.in_row[i];
+            if self.in_col[j] == i {
+                self.in_col[j] = std::usize::MAX;
+                self.free_rows.push(i);
+            }
+        }
+        self
+    }
+
     /// Returns a `Cancellation` token which can be cancelled from another thread.
     pub fn cancellation(&self) -> Cancellation {
         self.cancellation.clone()
     }
 
+    /// Uses the given `Cancellation` token, e.g., one shared with other solvers.
+    pub fn with_cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
     fn check_cancelled(&self) -> Result<(), LapJVError> {
         if self.cancellation.is_cancelled() {
             return Err(LapJVError { kind: ErrorKind::Cancelled });
@@ -133,9 +287,33 @@ where
         Ok(())
     }
 
-    pub fn solve(mut self) -> Result<(Vec<usize>, Vec<usize>), LapJVError> {
-        if self.costs.dim().0 != self.costs.dim().1 {
-            return Err(LapJVError { kind: ErrorKind::Msg("Input error: matrix is not square") } );
+    pub fn solve(self) -> Result<(Vec<usize>, Vec<usize>), LapJVError> {
+        let state = self.solve_with_state()?;
+        Ok((state.in_row, state.in_col))
+    }
+
+    /// Like `solve`, but returns the state needed for `lapjv_update`
+    pub fn solve_with_state(mut self) -> Result<LapJVState<T>, LapJVError> {
+        let (n_rows, n_cols) = self.costs.dim();
+        match self.unmatched {
+            None if n_rows != n_cols => {
+                return Err(LapJVError { kind: ErrorKind::Msg("Input error: matrix is not square") } );
+            }
+            Some((unmatched_rows, unmatched_cols))
+                if unmatched_rows.len() != n_rows || unmatched_cols.len() != n_cols =>
+            {
+                return Err(LapJVError {
+                    kind: ErrorKind::Msg("Input error: unmatched costs do not fit the matrix"),
+                });
+            }
+            _ => {}
+        }
+        if self.v.len() == self.dim {
+            // Warm start, so only the freed rows need augmenting paths.
+            if !self.free_rows.is_empty() {
+                self.ca_dense()?;
+            }
+            return Ok(self.into_state());
         }
         self.ccrrt_dense();
 
@@ -150,7 +328,15 @@ where
             self.ca_dense()?;
         }
 
-        Ok((self.in_row, self.in_col))
+        Ok(self.into_state())
+    }
+
+    fn into_state(self) -> LapJVState<T> {
+        LapJVState {
+            v: self.v,
+            in_row: self.in_row,
+            in_col: self.in_col,
+        }
     }
 
     // Column-reduction and reduction transfer for a dense cost matrix
@@ -158,10 +344,11 @@ where
         let mut unique = vec![true; self.dim];
         let mut in_row_not_set = vec![true; self.dim];
 
-        for row in self.costs.lanes(ndarray::Axis(0)) {
-            let (min_index, min_value) = row.indexed_iter().skip(1).fold(
-                (0, row[0]),
-                |(old_idx, old_min), (new_idx, &new_min)| {
+        for j in 0..self.dim {
+            let (min_index, min_value) = (1..self.dim).fold(
+                (0, self.cost(0, j)),
+                |(old_idx, old_min), new_idx| {
+                    let new_min = self.cost(new_idx, j);
                     if new_min < old_min {
                         (new_idx, new_min)
                     } else {
@@ -221,7 +408,8 @@ where
             let free_i = self.free_rows[current];
             current += 1;
             // find minimum and second minimum reduced cost over columns.
-            let (v1, v2, mut j1, j2) = find_umins_plain(self.costs.row(free_i), &self.v);
+            let (v1, v2, mut j1, j2) =
+                find_umins_plain((0..dim).map(|j| self.cost(free_i, j)), &self.v);
 
             let mut i0 = self.in_col[j1];
             let v1_new = self.v[j1] - (v2 - v1);
@@ -387,7 +575,19 @@ where
 
     #[inline(always)]
     fn cost(&self, i: usize, j: usize) -> T {
-        self.costs[(i, j)]
+        let (n_rows, n_cols) = self.costs.dim();
+        if i < n_rows && j < n_cols {
+            return self.costs[(i, j)];
+        }
+        // Only a rectangular problem has rows or columns beyond those of the cost matrix.
+        let (unmatched_rows, unmatched_cols) = self.unmatched.unwrap();
+        if i < n_rows {
+            unmatched_rows[i]
+        } else if j < n_cols {
+            unmatched_cols[j]
+        } else {
+            T::zero()
+        }
     }
 
     #[inline(always)]
@@ -422,16 +622,19 @@ where
 
 // Finds minimum and second minimum from a row, returns (min, second_min, min_index, second_min_index)
 #[inline(always)]
-fn find_umins_plain<T>(local_cost: ndarray::ArrayView1<T>, v: &[T]) -> (T, T, usize, Option<usize>)
+fn find_umins_plain<T>(
+    mut local_cost: impl Iterator<Item = T>,
+    v: &[T],
+) -> (T, T, usize, Option<usize>)
 where
     T: LapJVCost,
 {
-    let mut umin = local_cost[0] - v[0];
+    let mut umin = local_cost.next().unwrap() - v[0];
     let mut usubmin = T::max_value();
     let mut j1 = 0;
     let mut j2 = None;
-    for j in 1..local_cost.dim() {
-        let h = local_cost[j] - v[j];
+    for (j, c) in local_cost.enumerate().map(|(j, c)| (j + 1, c)) {
+        let h = c - v[j];
         if h < usubmin {
             if h >= umin {
                 usubmin = h;
@@ -470,6 +673,83 @@ mod tests {
         cancellation.cancel();
         let result = lapjv.solve();
         assert!(matches!(result, Err(LapJVError { kind: ErrorKind::Cancelled })));
+        let cancellation = Cancellation::default();
+        let mut state = lapjv_with_state(&m).unwrap();
+        assert!(LapJV::new(&m).with_cancellation(cancellation.clone()).solve().is_ok());
+        cancellation.cancel();
+        let result = lapjv_update_with_cancellation(&m, &mut state, &[0], &cancellation);
+        assert!(matches!(result, Err(LapJVError { kind: ErrorKind::Cancelled })));
+        assert!(state.in_row().is_empty());
+    }
+
+    #[test]
+    fn warm_start_after_row_updates() {
+        const DIM: usize = 40;
+        let mut m = Matrix::from_shape_fn((DIM, DIM), |_| (rand::random::<f64>() * 100.0).round());
+        let mut state = lapjv_with_state(&m).unwrap();
+        for step in 0..200 {
+            let rows: Vec<usize> = (0..1 + step % 3).map(|_| rand::random::<usize>() % DIM).collect();
+            for &i in &rows {
+                for j in 0..DIM {
+                    m[(i, j)] = (rand::random::<f64>() * 100.0).round();
+                }
+            }
+            lapjv_update(&m, &mut state, &rows).unwrap();
+            let expected = lapjv(&m).unwrap();
+            assert_eq!(cost(&m, state.in_row()), cost(&m, &expected.0));
+            for (i, &j) in state.in_row().iter().enumerate() {
+                assert_eq!(state.in_col()[j], i);
+            }
+        }
+        let smaller = Matrix::from_shape_fn((3, 3), |(i, j)| (i * j) as f64);
+        lapjv_update(&smaller, &mut state, &[0]).unwrap();
+        assert_eq!(state.in_row().len(), 3);
+    }
+
+    #[test]
+    fn rectangular_agrees_with_padding() {
+        for &(n_rows, n_cols) in &[(3, 7), (7, 3), (5, 5), (0, 4)] {
+            let dim = std::cmp::max(n_rows, n_cols);
+            let random = || (rand::random::<f64>() * 100.0).round();
+            let mut m = Matrix::from_shape_fn((n_rows, n_cols), |_| random());
+            let mut unmatched_rows: Vec<f64> = (0..n_rows).map(|_| random()).collect();
+            let unmatched_cols: Vec<f64> = (0..n_cols).map(|_| random()).collect();
+            let padded = |m: &Matrix<f64>, unmatched_rows: &[f64]| {
+                Matrix::from_shape_fn((dim, dim), |(i, j)| match (i < n_rows, j < n_cols) {
+                    (true, true) => m[(i, j)],
+                    (true, false) => unmatched_rows[i],
+                    (false, true) => unmatched_cols[j],
+                    (false, false) => 0.0,
+                })
+            };
+            let mut state = LapJV::rectangular(&m, &unmatched_rows, &unmatched_cols)
+                .solve_with_state()
+                .unwrap();
+            assert_eq!(state.in_row().len(), dim);
+            for step in 0..20 {
+                if n_rows > 0 {
+                    let i = step % n_rows;
+                    for j in 0..n_cols {
+                        m[(i, j)] = random();
+                    }
+                    unmatched_rows[i] = random();
+                    state = LapJV::rectangular(&m, &unmatched_rows, &unmatched_cols)
+                        .with_state(state, &[i])
+                        .solve_with_state()
+                        .unwrap();
+                }
+                let square = padded(&m, &unmatched_rows);
+                let expected = lapjv(&square).unwrap();
+                assert_eq!(
+                    cost_rectangular(&m, &unmatched_rows, &unmatched_cols, state.in_row()),
+                    cost(&square, &expected.0)
+                );
+                assert_eq!(cost(&square, state.in_row()), cost(&square, &expected.0));
+            }
+        }
+        let m = Matrix::zeros((2, 3));
+        assert!(LapJV::new(&m).solve().is_err());
+        assert!(LapJV::rectangular(&m, &[0.0], &[0.0; 3]).solve().is_err());
     }
 
     #[test]
@@ -595,7 +875,7 @@ mod tests {
     fn test_find_umins() {
         let m = Matrix::from_shape_vec((3, 3), vec![25.0, 0.0, 15.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0])
             .unwrap();
-        let result = find_umins_plain(m.row(0), &vec![0.0, 0.0, 0.0]);
+        let result = find_umins_plain(m.row(0).iter().copied(), &vec![0.0, 0.0, 0.0]);
         println!("Result: {:?}", result);
         assert_eq!(result, (0.0, 15.0, 1, Some(2)));
     }